@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context, anyhow, bail};
+use serde::Deserialize;
+use trace_rpc::{ClientFlavor, Compression, RetryPolicy, TraceConfig, TracerOptions};
+
+// project local config, checked before the per-user one
+const LOCAL_CONFIG: &str = "opentracer.toml";
+
+// Example:
+//
+//   default_profile = "mainnet"
+//
+//   [profiles.mainnet]
+//   rpc_url = "https://eth-mainnet.example.com/v2/${MAINNET_KEY}"
+//   chain_id = 1
+//   concurrency = 8
+//   headers = { Authorization = "Bearer ${MAINNET_TOKEN}" }
+//   tracer = { tracer = { type = "structLogger", enableMemory = false }, timeout = "60s" }
+//
+//   [profiles.anvil]
+//   rpc_url = "http://localhost:8545"
+//   client = "anvil"
+//
+// ${VAR} in rpc_url and header values is read from the environment, so keys stay out of the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+// one named endpoint, every field maps onto TraceConfig and is optional
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub rpc_url: Option<String>,
+    pub out_dir: Option<PathBuf>,
+    pub client: Option<ClientFlavor>,
+    pub chain_id: Option<u64>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub tracer: Option<TracerOptions>,
+    pub concurrency: Option<usize>,
+    pub compression: Option<Compression>,
+    pub retry: Option<RetryPolicy>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("could not read config {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    // the named profile, else default_profile, else nothing
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<Profile>> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(None);
+        };
+        let profile = self.profiles.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            anyhow!("no profile named {:?} (known: {})", name, known.join(", "))
+        })?;
+        Ok(Some(profile.clone()))
+    }
+}
+
+// --config, else ./opentracer.toml, else $XDG_CONFIG_HOME/opentracer/config.toml (~/.config by default).
+// an explicitly given file has to exist, the implicit ones are optional
+pub fn load(explicit: Option<&Path>) -> Result<ConfigFile> {
+    if let Some(path) = explicit {
+        return ConfigFile::load(path);
+    }
+
+    let candidates = [Some(PathBuf::from(LOCAL_CONFIG)), user_config()];
+    for path in candidates.into_iter().flatten() {
+        if path.is_file() {
+            return ConfigFile::load(&path);
+        }
+    }
+    Ok(ConfigFile::default())
+}
+
+fn user_config() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("opentracer").join("config.toml"))
+}
+
+impl Profile {
+    // copies every field the profile sets onto the config
+    pub fn apply(&self, config: &mut TraceConfig) -> Result<()> {
+        self.apply_with(config, |name| env::var(name).ok())
+    }
+
+    // like apply, with ${VAR} looked up through `lookup` instead of the process environment
+    fn apply_with(&self, config: &mut TraceConfig, lookup: impl Fn(&str) -> Option<String>) -> Result<()> {
+        if let Some(rpc_url) = &self.rpc_url {
+            config.rpc_url = expand_env(rpc_url, &lookup)?;
+        }
+        if let Some(out_dir) = &self.out_dir {
+            config.out_dir = out_dir.clone();
+        }
+        if self.client.is_some() {
+            config.client = self.client;
+        }
+        if self.chain_id.is_some() {
+            config.chain_id = self.chain_id;
+        }
+        for (name, value) in &self.headers {
+            config.headers.insert(name.clone(), expand_env(value, &lookup)?);
+        }
+        if let Some(tracer) = &self.tracer {
+            config.tracer = tracer.clone();
+        }
+        if let Some(concurrency) = self.concurrency {
+            config.concurrency = concurrency;
+        }
+        if let Some(compression) = self.compression {
+            config.compression = compression;
+        }
+        if let Some(retry) = &self.retry {
+            config.retry = retry.clone();
+        }
+        Ok(())
+    }
+}
+
+// replaces ${VAR} with the variable's value, unset variables are an error
+fn expand_env(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            bail!("unterminated ${{ in {:?}", value);
+        };
+        let name = &rest[start + 2..start + end];
+        let var = lookup(name).with_context(|| format!("environment variable {} is not set", name))?;
+        out.push_str(&var);
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use trace_rpc::Tracer;
+
+    #[test]
+    fn test_profile_applies_onto_config() {
+        let file: ConfigFile = toml::from_str(r#"
+            default_profile = "local"
+
+            [profiles.local]
+            rpc_url = "http://localhost:8545"
+            client = "anvil"
+
+            [profiles.mainnet]
+            rpc_url = "https://eth.example.com/${OPENTRACER_TEST_HOME}"
+            chain_id = 1
+            concurrency = 8
+            compression = "zstd"
+            headers = { Authorization = "Bearer abc" }
+            tracer = { tracer = { type = "callTracer", onlyTopCall = true, withLog = false }, timeout = "60s" }
+        "#).unwrap();
+
+        let local = file.profile(None).unwrap().unwrap();
+        assert_eq!(local.client, Some(ClientFlavor::Anvil));
+        assert!(file.profile(Some("sepolia")).is_err());
+
+        let vars = HashMap::from([("OPENTRACER_TEST_HOME", "key")]);
+        let lookup = |name: &str| vars.get(name).map(|value| value.to_string());
+        let mut config = TraceConfig::new(String::new(), PathBuf::from("out"));
+        file.profile(Some("mainnet")).unwrap().unwrap().apply_with(&mut config, lookup).unwrap();
+
+        assert_eq!(config.rpc_url, "https://eth.example.com/key");
+        assert_eq!(config.chain_id, Some(1));
+        assert_eq!(config.concurrency, 8);
+        assert_eq!(config.compression, Compression::Zstd);
+        assert_eq!(config.headers["Authorization"], "Bearer abc");
+        assert_eq!(config.tracer.tracer, Tracer::CallTracer { only_top_call: true, with_log: false });
+        assert_eq!(config.out_dir, PathBuf::from("out"));
+    }
+
+    #[test]
+    fn test_expand_env() {
+        let lookup = |name: &str| (name == "HOST").then(|| "localhost".to_string());
+        assert_eq!(expand_env("no vars", lookup).unwrap(), "no vars");
+        assert_eq!(expand_env("http://${HOST}:8545", lookup).unwrap(), "http://localhost:8545");
+        assert!(expand_env("${UNSET}", lookup).is_err());
+        assert!(expand_env("${OPEN", lookup).is_err());
+    }
+}
@@ -1,32 +1,852 @@
-use std::path::{PathBuf};
-use trace_rpc::{TraceConfig, TraceFetcher};
-use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use anyhow::{Result, Context, bail};
+use clap::{Parser, Subcommand, Args, ValueEnum};
+use serde::Serialize;
+use trace_ir::{AbiRegistry, CallFrame, StateDiff, StepFormatter, StructLogReader, TreeRenderer, Word};
+use trace_ir::analysis::CallTreeBuilder;
+use trace_ir::render::format_ether;
+use trace_rpc::{
+    BatchItem, BlockId, ClientFlavor, Compression, Receipt, StructLoggerOptions, TraceConfig, TraceFetcher, TraceMetadata,
+    TraceStore, Tracer, Transaction, check_tx_hash, normalize_tx_hash, validate_trace_file,
+};
 
+mod config;
+
+const DEFAULT_OUT_DIR: &str = "./data/raw_traces";
+
+#[derive(Parser)]
+#[command(name = "opentracer", version, about = "Fetch, validate and inspect EVM execution traces")]
+struct Cli {
+    /// config file, defaults to ./opentracer.toml or ~/.config/opentracer/config.toml
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// named profile from the config file
+    #[arg(long, env = "OPENTRACER_PROFILE", global = true)]
+    profile: Option<String>,
+
+    /// JSON-RPC endpoint of a node with the debug namespace enabled
+    #[arg(long, env = "TRACE_RPC_URL", global = true, hide_env_values = true)]
+    rpc_url: Option<String>,
+
+    /// directory holding one <tx_hash>/ folder per fetched trace [default: ./data/raw_traces]
+    #[arg(long, env = "TRACE_OUT_DIR", global = true)]
+    out_dir: Option<PathBuf>,
+
+    /// node client, detected with web3_clientVersion when not set
+    #[arg(long, value_enum, global = true)]
+    client: Option<ClientArg>,
+
+    #[arg(long, value_enum, default_value_t = Format::Text, global = true)]
+    format: Format,
+
+    /// fail on opcodes this build does not know instead of passing them through
+    #[arg(long, global = true)]
+    strict: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// fetch trace, receipt and metadata for one or more transactions
+    Fetch {
+        #[arg(required = true)]
+        tx_hashes: Vec<String>,
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+    /// fetch every transaction of a block with one debug_traceBlock call
+    FetchBlock {
+        /// block number (decimal or 0x hex) or block hash
+        block: BlockId,
+        #[command(flatten)]
+        fetch: FetchArgs,
+    },
+    /// check saved trace or receipt files, or whole tx directories
+    Validate {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// print the call tree of a transaction
+    Tree {
+        /// tx hash (looked up in --out-dir, fetched when missing) or path to a trace file
+        target: String,
+        #[command(flatten)]
+        view: TreeArgs,
+    },
+    /// print an opcode level execution listing with stack and memory changes
+    Steps {
+        /// tx hash (looked up in --out-dir, fetched when missing) or path to a trace file
+        target: String,
+        #[command(flatten)]
+        filter: StepArgs,
+    },
+    /// what the transaction changed (balances, nonces, storage, new contracts), rebuilt from the trace
+    Diff {
+        /// tx hash (looked up in --out-dir, fetched when missing) or a tx directory or trace file fetch wrote
+        target: String,
+        /// cross-check against the node's prestateTracer diff, fails when they disagree
+        #[arg(long)]
+        check: bool,
+    },
+    /// summarize a saved trace
+    Stats {
+        /// trace file or tx directory
+        path: PathBuf,
+    },
+}
+
+// unset flags fall back to the profile, then to TraceConfig's defaults
+#[derive(Args)]
+struct FetchArgs {
+    /// [default: struct]
+    #[arg(long, value_enum)]
+    tracer: Option<TracerArg>,
+
+    /// node side tracer timeout, go duration syntax (e.g. 60s)
+    #[arg(long)]
+    timeout: Option<String>,
+
+    /// max transactions downloaded in parallel [default: 4]
+    #[arg(long)]
+    concurrency: Option<usize>,
+
+    /// [default: none]
+    #[arg(long, value_enum)]
+    compression: Option<CompressionArg>,
+
+    /// download again even when a valid copy is already in --out-dir
+    #[arg(long)]
+    no_cache: bool,
+}
+
+#[derive(Args)]
+struct TreeArgs {
+    /// only print this many levels below the top-level call
+    #[arg(long)]
+    depth: Option<usize>,
+
+    /// show how many steps each frame executed
+    #[arg(long)]
+    steps: bool,
+
+    /// print full checksummed addresses instead of 0xabcd…ef01
+    #[arg(long)]
+    full_addresses: bool,
+
+    #[arg(long, value_enum, default_value_t = ColorArg::Auto)]
+    color: ColorArg,
+
+    /// extra ABIs named <address>.json, decoded ahead of the bundled signatures
+    #[arg(long, value_name = "DIR")]
+    abi_dir: Option<PathBuf>,
+}
+
+// filters only pick which steps are printed, diffs are always against the step executed before
+#[derive(Args)]
+struct StepArgs {
+    /// only steps at this call depth, the top-level call is 1
+    #[arg(long)]
+    depth: Option<u64>,
+
+    /// only steps with START <= pc < END, or a single pc
+    #[arg(long, value_name = "START..END", value_parser = parse_pc_range)]
+    pc: Option<Range<u64>>,
+
+    /// only these opcodes, repeatable (--op SSTORE --op CALL)
+    #[arg(long = "op", value_name = "NAME")]
+    ops: Vec<String>,
+
+    /// stack items shown per step, from the top
+    #[arg(long, default_value_t = 4)]
+    stack: usize,
+
+    #[arg(long, value_enum, default_value_t = ColorArg::Auto)]
+    color: ColorArg,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorArg {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TracerArg {
+    Struct,
+    Call,
+    Prestate,
+    #[value(name = "4byte")]
+    FourByte,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ClientArg {
+    Geth,
+    Erigon,
+    Reth,
+    Nethermind,
+    Anvil,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CompressionArg {
+    None,
+    Zstd,
+    Gzip,
+}
+
+impl From<TracerArg> for Tracer {
+    fn from(arg: TracerArg) -> Self {
+        match arg {
+            TracerArg::Struct => Tracer::StructLogger(StructLoggerOptions::default()),
+            TracerArg::Call => Tracer::CallTracer { only_top_call: false, with_log: true },
+            TracerArg::Prestate => Tracer::PrestateTracer { diff_mode: false },
+            TracerArg::FourByte => Tracer::FourByteTracer,
+        }
+    }
+}
+
+impl From<ClientArg> for ClientFlavor {
+    fn from(arg: ClientArg) -> Self {
+        match arg {
+            ClientArg::Geth => ClientFlavor::Geth,
+            ClientArg::Erigon => ClientFlavor::Erigon,
+            ClientArg::Reth => ClientFlavor::Reth,
+            ClientArg::Nethermind => ClientFlavor::Nethermind,
+            ClientArg::Anvil => ClientFlavor::Anvil,
+        }
+    }
+}
+
+impl From<CompressionArg> for Compression {
+    fn from(arg: CompressionArg) -> Self {
+        match arg {
+            CompressionArg::None => Compression::None,
+            CompressionArg::Zstd => Compression::Zstd,
+            CompressionArg::Gzip => Compression::Gzip,
+        }
+    }
+}
+
+// exit codes: 0 everything worked, 1 something failed, 2 bad usage (clap)
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(&cli).await {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Ok(false) when the command ran but some of its items failed
+async fn run(cli: &Cli) -> Result<bool> {
+    match &cli.command {
+        Command::Fetch { tx_hashes, fetch } => {
+            let fetcher = fetcher(trace_config(cli, Some(fetch))?).await?;
+            let items = fetcher.fetch_many(tx_hashes).await;
+            Ok(report_batch(cli.format, &items))
+        }
+        Command::FetchBlock { block, fetch } => {
+            let fetcher = fetcher(trace_config(cli, Some(fetch))?).await?;
+            let items = fetcher.fetch_block(block).await?;
+            Ok(report_batch(cli.format, &items))
+        }
+        Command::Validate { paths } => Ok(validate(cli.format, paths, cli.strict)),
+        Command::Tree { target, view } => {
+            let (trace_path, client, builder) = tree_trace(cli, target).await?;
+            let mut root = build_tree(&trace_path, builder, client, cli.strict)?;
+            let mut abis = AbiRegistry::new();
+            if let Some(dir) = &view.abi_dir {
+                abis = abis.load_dir(dir)?;
+            }
+            abis.annotate(&mut root);
+            match cli.format {
+                Format::Json => print_json(&root)?,
+                Format::Text => {
+                    let renderer = TreeRenderer::new()
+                        .max_depth(view.depth)
+                        .step_counts(view.steps)
+                        .full_addresses(view.full_addresses)
+                        .color(use_color(view.color));
+                    print!("{}", renderer.render(&root));
+                }
+            }
+            Ok(true)
+        }
+        Command::Steps { target, filter } => {
+            let (trace_path, client) = resolve_trace(trace_config(cli, None)?, target).await?;
+            print_steps(&trace_path, client, filter, cli.format, cli.strict)?;
+            Ok(true)
+        }
+        Command::Diff { target, check } => diff(cli, target, *check).await,
+        Command::Stats { path } => {
+            let (trace_path, client) = resolve_trace(trace_config(cli, None)?, &path.to_string_lossy()).await?;
+            let stats = TraceStats::collect(&trace_path, client, cli.strict)?;
+            match cli.format {
+                Format::Json => print_json(&stats)?,
+                Format::Text => stats.print(),
+            }
+            Ok(true)
+        }
+    }
+}
+
+// defaults, then the profile, then env vars and flags (clap merges those two)
+fn trace_config(cli: &Cli, fetch: Option<&FetchArgs>) -> Result<TraceConfig> {
+    let mut config = TraceConfig::new(String::new(), PathBuf::from(DEFAULT_OUT_DIR));
+
+    let file = config::load(cli.config.as_deref())?;
+    if let Some(profile) = file.profile(cli.profile.as_deref())? {
+        profile.apply(&mut config)?;
+    }
+
+    if let Some(rpc_url) = &cli.rpc_url {
+        config.rpc_url = rpc_url.clone();
+    }
+    if let Some(out_dir) = &cli.out_dir {
+        config.out_dir = out_dir.clone();
+    }
+    if let Some(client) = cli.client {
+        config.client = Some(client.into());
+    }
+    if let Some(fetch) = fetch {
+        if let Some(tracer) = fetch.tracer {
+            config.tracer.tracer = tracer.into();
+        }
+        if fetch.timeout.is_some() {
+            config.tracer.timeout = fetch.timeout.clone();
+        }
+        if let Some(concurrency) = fetch.concurrency {
+            config.concurrency = concurrency;
+        }
+        if let Some(compression) = fetch.compression {
+            config.compression = compression.into();
+        }
+        config.use_cache = !fetch.no_cache;
+    }
+    Ok(config)
+}
+
+async fn fetcher(config: TraceConfig) -> Result<TraceFetcher> {
+    if config.rpc_url.is_empty() {
+        bail!("no RPC endpoint, pass --rpc-url, set TRACE_RPC_URL or pick a --profile with an rpc_url");
+    }
+
+    // only ask the node when the client was not given
+    if config.client.is_some() {
+        Ok(TraceFetcher::new(config))
+    } else {
+        TraceFetcher::detect(config).await
+    }
+}
+
+#[derive(Serialize)]
+struct FetchReport<'a> {
+    tx_hash: &'a str,
+    ok: bool,
+    trace_path: Option<&'a Path>,
+    error: Option<String>,
+}
+
+fn report_batch(format: Format, items: &[BatchItem]) -> bool {
+    let reports: Vec<FetchReport> = items
+        .iter()
+        .map(|item| FetchReport {
+            tx_hash: &item.tx_hash,
+            ok: item.result.is_ok(),
+            trace_path: item.result.as_ref().ok().map(|t| t.trace_path.as_path()),
+            error: item.result.as_ref().err().map(|e| format!("{:#}", e)),
+        })
+        .collect();
+
+    match format {
+        Format::Json => {
+            if let Err(e) = print_json(&reports) {
+                eprintln!("error: {:#}", e);
+                return false;
+            }
+        }
+        Format::Text => {
+            for report in &reports {
+                match (&report.trace_path, &report.error) {
+                    (Some(path), _) => println!("ok      {} {}", report.tx_hash, path.display()),
+                    (None, Some(error)) => println!("failed  {} {}", report.tx_hash, error),
+                    (None, None) => unreachable!("a batch item is either ok or failed"),
+                }
+            }
+        }
+    }
+
+    reports.iter().all(|r| r.ok)
+}
+
+#[derive(Serialize)]
+struct ValidationReport {
+    path: PathBuf,
+    ok: bool,
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skipped: Option<String>,        // why a check could not run, skipped checks count as ok
+}
+
+fn validate(format: Format, paths: &[PathBuf], strict: bool) -> bool {
+    let mut reports = Vec::new();
+    for path in paths {
+        // a tx directory stands for the files fetch writes into it
+        let files = if path.is_dir() {
+            vec![path.join("trace.json"), path.join("receipt.json")]
+        } else {
+            vec![path.clone()]
+        };
+        let mut files_ok = true;
+        for file in files {
+            let result = validate_trace_file(&file);
+            files_ok &= result.is_ok();
+            reports.push(ValidationReport {
+                ok: result.is_ok(),
+                error: result.err().map(|e| format!("{:#}", e)),
+                skipped: None,
+                path: file,
+            });
+        }
+
+        // both files parse, now check that the trace and the receipt tell the same story
+        if path.is_dir() && files_ok {
+            let result = check_logs(path, strict);
+            reports.push(ValidationReport {
+                ok: result.is_ok(),
+                skipped: result.as_ref().ok().cloned().flatten(),
+                error: result.err().map(|e| format!("{:#}", e)),
+                path: path.clone(),
+            });
+        }
+    }
+
+    match format {
+        Format::Json => {
+            if let Err(e) = print_json(&reports) {
+                eprintln!("error: {:#}", e);
+                return false;
+            }
+        }
+        Format::Text => {
+            for report in &reports {
+                match (&report.error, &report.skipped) {
+                    (Some(error), _) => println!("invalid  {} {}", report.path.display(), error),
+                    (None, Some(reason)) => println!("skipped  {} {}", report.path.display(), reason),
+                    (None, None) => println!("valid    {}", report.path.display()),
+                }
+            }
+        }
+    }
 
-    let rpc_url = "https://mainnet.gateway.tenderly.co/68FIYvi1epfk2HlzP0XAMz".to_string();
-    let out_dir = PathBuf::from("./data/raw_traces");
-    let tx_hash = "0x2d8edc881796aff96a5c6177665c7b3c7266108f23c9732a8c21a9771277d8c5";
+    reports.iter().all(|r| r.ok)
+}
 
-    let config = TraceConfig{
-        rpc_url, 
-        out_dir
+// the logs the trace emits and keeps have to be exactly the receipt's.
+// Ok(Some(reason)) when the trace cannot tell, logs are only recoverable from complete
+// structLogs with memory and stack
+fn check_logs(tx_dir: &Path, strict: bool) -> Result<Option<String>> {
+    let Some(metadata) = stored_metadata(tx_dir)? else {
+        return Ok(Some("log check needs metadata.json".to_string()));
     };
-    
-    let fetcher = TraceFetcher::new(config);
+    let complete = |o: &StructLoggerOptions| o.enable_memory && !o.disable_stack && o.limit.is_none_or(|l| l == 0);
+    match &metadata.tracer.tracer {
+        Tracer::StructLogger(options) if complete(options) => {}
+        Tracer::StructLogger(_) => {
+            return Ok(Some("log check needs a structLogger trace with memory and stack, unlimited".to_string()));
+        }
+        _ => return Ok(Some("log check needs a structLogger trace".to_string())),
+    }
 
-    match fetcher.fetch_transaction(tx_hash).await {
-        Ok(raw_trace) => {
-            println!("success!");
-            println!("trace saved to: {:?} ", raw_trace.trace_path);
-        },
-        Err(e) => {
-            eprintln!("Error fetching tx trace: {:?}", e);
+    let receipt = Receipt::read(&tx_dir.join("receipt.json"))?;
+    let target = receipt.target().context("receipt has neither to nor contractAddress")?;
+    let builder = CallTreeBuilder::new().with_transaction(receipt.from, target, Word::ZERO, Vec::new());
+    let root = build_tree(&tx_dir.join("trace.json"), builder, metadata.client, strict)?;
+    receipt.verify_logs(&root).context("logs do not match the receipt")?;
+    Ok(None)
+}
+
+// a tx hash, a tx directory or a trace file, and the client whose depth convention it uses
+async fn resolve_trace(config: TraceConfig, target: &str) -> Result<(PathBuf, ClientFlavor)> {
+    if let Some(found) = local_trace(&config, target)? {
+        return Ok(found);
+    }
+    let trace = fetcher(config).await?.fetch_transaction(target).await?;
+    Ok((trace.trace_path, trace.client))
+}
+
+// like resolve_trace, but None where the trace would have to be fetched
+fn local_trace(config: &TraceConfig, target: &str) -> Result<Option<(PathBuf, ClientFlavor)>> {
+    let path = Path::new(target);
+    if path.exists() {
+        let (trace_path, dir) = if path.is_dir() {
+            (path.join("trace.json"), Some(path))
+        } else {
+            (path.to_path_buf(), path.parent())
+        };
+        // metadata next to the trace knows the client, --client wins, geth otherwise
+        let client = match config.client {
+            Some(client) => client,
+            None => dir.and_then(stored_client).unwrap_or_default(),
+        };
+        return Ok(Some((trace_path, client)));
+    }
+
+    if !target.starts_with("0x") {
+        bail!("{} is neither a file nor a tx hash", target);
+    }
+
+    let store = TraceStore::new(config.out_dir.clone());
+    if let Ok(Some(trace)) = store.get(target) {
+        return Ok(Some((trace.trace_path, trace.client)));
+    }
+    Ok(None)
+}
+
+fn stored_client(tx_dir: &Path) -> Option<ClientFlavor> {
+    stored_metadata(tx_dir).ok().flatten().map(|m| m.client)
+}
+
+fn stored_metadata(tx_dir: &Path) -> Result<Option<TraceMetadata>> {
+    let (Some(root), Some(tx_hash)) = (tx_dir.parent(), tx_dir.file_name().and_then(|n| n.to_str())) else {
+        return Ok(None);
+    };
+    // only directories fetch created are named after a tx hash
+    if check_tx_hash(tx_hash).is_err() {
+        return Ok(None);
+    }
+    TraceStore::new(root).metadata(tx_hash)
+}
+
+fn use_color(arg: ColorArg) -> bool {
+    match arg {
+        ColorArg::Auto => std::io::stdout().is_terminal(),
+        ColorArg::Always => true,
+        ColorArg::Never => false,
+    }
+}
+
+fn parse_pc_range(s: &str) -> Result<Range<u64>> {
+    let parse = |n: &str| -> Result<u64> {
+        match n.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).with_context(|| format!("invalid pc {:?}", n)),
+            None => n.parse().with_context(|| format!("invalid pc {:?}", n)),
+        }
+    };
+    let (start, end) = match s.split_once("..") {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => {
+            let pc = parse(s)?;
+            (pc, pc.checked_add(1).with_context(|| format!("pc {} is past the last one", pc))?)
         }
+    };
+    // an empty range would silently print nothing
+    if start >= end {
+        bail!("pc range {:?} is empty, the end is exclusive", s);
+    }
+    Ok(start..end)
+}
+
+fn print_steps(trace_path: &Path, client: ClientFlavor, filter: &StepArgs, format: Format, strict: bool) -> Result<()> {
+    let mut formatter = StepFormatter::new().stack_items(filter.stack).color(use_color(filter.color));
+    if format == Format::Text {
+        println!("{}", formatter.header());
     }
 
+    let mut reader = StructLogReader::open(trace_path)?.quirks(client.quirks()).strict(strict);
+    for step in reader.by_ref() {
+        let step = step?;
+
+        let wanted = filter.depth.is_none_or(|d| d == step.depth)
+            && filter.pc.as_ref().is_none_or(|pcs| pcs.contains(&step.pc))
+            && (filter.ops.is_empty() || filter.ops.iter().any(|op| op.eq_ignore_ascii_case(step.info().name)));
+
+        match (wanted, format) {
+            (false, _) => formatter.skip(&step),
+            (true, Format::Text) => println!("{}", formatter.format(&step)),
+            // one instruction per line, streams like the trace itself
+            (true, Format::Json) => println!("{}", serde_json::to_string(&step)?),
+        }
+    }
+    warn_unknown(&reader);
     Ok(())
+}
+
+fn build_tree(trace_path: &Path, builder: CallTreeBuilder, client: ClientFlavor, strict: bool) -> Result<CallFrame> {
+    let mut builder = builder.store_instructions(false);
+    let mut reader = StructLogReader::open(trace_path)?.quirks(client.quirks()).strict(strict);
+    for step in reader.by_ref() {
+        builder.push(step?)?;
+    }
+    warn_unknown(&reader);
+    builder.finish().context("could not build call tree")
+}
+
+// the node emitted opcodes this build has no entry for, analysis of them is a guess
+fn warn_unknown(reader: &StructLogReader) {
+    if reader.unknown_opcodes() > 0 {
+        eprintln!("warning: {} step(s) with unknown opcodes, rerun with --strict to stop at the first", reader.unknown_opcodes());
+    }
+    if reader.dropped_opcode_names() > 0 {
+        eprintln!("warning: the names of {} unknown opcode step(s) were not kept", reader.dropped_opcode_names());
+    }
+}
+
+#[derive(Serialize)]
+struct DiffReport<'a> {
+    diff: &'a StateDiff,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mismatches: Option<Vec<String>>,
+}
 
+// the trace lacks the tx's sender, value and input, so those come from the node
+fn transaction_builder(tx: &Transaction, trace_path: &Path) -> Result<CallTreeBuilder> {
+    let to = match tx.to {
+        Some(to) => to,
+        None => {
+            let receipt_path = trace_path.with_file_name("receipt.json");
+            Receipt::read(&receipt_path)?.target().context("receipt of a contract creation has no contractAddress")?
+        }
+    };
+    Ok(CallTreeBuilder::new().with_transaction(tx.from, to, tx.value, tx.input.to_vec()))
+}
 
-}
\ No newline at end of file
+// the trace and a builder seeded with its tx, both from one config and at most one node connection.
+// a cached trace only asks the node when one is configured and falls back to its stored receipt
+async fn tree_trace(cli: &Cli, target: &str) -> Result<(PathBuf, ClientFlavor, CallTreeBuilder)> {
+    let config = trace_config(cli, None)?;
+    if let Some((trace_path, client)) = local_trace(&config, target)? {
+        let builder = match tx_hash_of(target, &trace_path) {
+            Some(tx_hash) if !config.rpc_url.is_empty() => {
+                let tx = match fetcher(config).await {
+                    Ok(fetcher) => fetcher.transaction(&tx_hash).await,
+                    Err(err) => Err(err),
+                };
+                tree_builder(tx, &trace_path)?
+            }
+            _ => receipt_builder(&trace_path)?,
+        };
+        return Ok((trace_path, client, builder));
+    }
+
+    let fetcher = fetcher(config).await?;
+    let trace = fetcher.fetch_transaction(target).await?;
+    let builder = match tx_hash_of(target, &trace.trace_path) {
+        Some(tx_hash) => tree_builder(fetcher.transaction(&tx_hash).await, &trace.trace_path)?,
+        None => receipt_builder(&trace.trace_path)?,
+    };
+    Ok((trace.trace_path, trace.client, builder))
+}
+
+// the root is seeded from the tx, from the receipt next to the trace when the node could not give it
+fn tree_builder(tx: Result<Transaction>, trace_path: &Path) -> Result<CallTreeBuilder> {
+    match tx {
+        Ok(tx) => transaction_builder(&tx, trace_path),
+        Err(err) if trace_path.with_file_name("receipt.json").is_file() => {
+            eprintln!("warning: {:#}, seeding the root from the stored receipt", err);
+            receipt_builder(trace_path)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// the receipt lacks value and input, a bare trace file leaves the root unseeded
+fn receipt_builder(trace_path: &Path) -> Result<CallTreeBuilder> {
+    let receipt_path = trace_path.with_file_name("receipt.json");
+    if !receipt_path.is_file() {
+        return Ok(CallTreeBuilder::new());
+    }
+    let receipt = Receipt::read(&receipt_path)?;
+    let target = receipt.target().context("receipt has neither to nor contractAddress")?;
+    Ok(CallTreeBuilder::new().with_transaction(receipt.from, target, Word::ZERO, Vec::new()))
+}
+
+// the target itself, or the tx directory the trace was stored in
+fn tx_hash_of(target: &str, trace_path: &Path) -> Option<String> {
+    if target.starts_with("0x") && !Path::new(target).exists() {
+        return normalize_tx_hash(target).ok();
+    }
+    let dir = trace_path.parent()?.file_name()?.to_str()?;
+    normalize_tx_hash(dir).ok()
+}
+
+async fn diff(cli: &Cli, target: &str, check: bool) -> Result<bool> {
+    let config = trace_config(cli, None)?;
+    let local = local_trace(&config, target)?;
+    // the node is asked about the tx, so a path has to name one
+    let tx_hash = match &local {
+        Some((trace_path, _)) => tx_hash_of(target, trace_path),
+        None => Some(normalize_tx_hash(target)?),
+    };
+    let tx_hash = tx_hash.with_context(|| format!("{} is not stored under a tx hash, diff needs one", target))?;
+
+    let fetcher = fetcher(config).await?;
+    let (trace_path, client) = match local {
+        Some(found) => found,
+        None => {
+            let trace = fetcher.fetch_transaction(&tx_hash).await?;
+            (trace.trace_path, trace.client)
+        }
+    };
+    let tx = fetcher.transaction(&tx_hash).await?;
+    let builder = transaction_builder(&tx, &trace_path)?;
+    let root = build_tree(&trace_path, builder, client, cli.strict)?;
+    let diff = StateDiff::from_call_tree(&root, tx.to.is_none());
+
+    let mismatches = if check {
+        Some(diff.compare(&fetcher.prestate_diff(&tx_hash).await?))
+    } else {
+        None
+    };
+    let agrees = mismatches.as_ref().is_none_or(|m| m.is_empty());
+
+    match cli.format {
+        Format::Json => print_json(&DiffReport {
+            diff: &diff,
+            mismatches: mismatches.map(|m| m.iter().map(|e| e.to_string()).collect()),
+        })?,
+        Format::Text => {
+            for (address, account) in &diff.accounts {
+                let mut line = address.to_string();
+                if account.created {
+                    line.push_str("  created");
+                }
+                if account.nonce_delta > 0 {
+                    line.push_str(&format!("  nonce +{}", account.nonce_delta));
+                }
+                if account.received > account.sent {
+                    line.push_str(&format!("  +{}", format_ether(account.received.wrapping_sub(account.sent).into())));
+                } else if account.sent > account.received {
+                    line.push_str(&format!("  -{}", format_ether(account.sent.wrapping_sub(account.received).into())));
+                }
+                if let Some(beneficiary) = account.self_destructed {
+                    line.push_str(&format!("  selfdestruct -> {}", beneficiary));
+                }
+                println!("{}", line);
+                for (slot, write) in &account.storage {
+                    let original = write.original.map(|w| w.to_string()).unwrap_or_else(|| "?".to_string());
+                    println!("  [{}] {} -> {}", slot, original, write.new);
+                }
+            }
+            match &mismatches {
+                Some(m) if m.is_empty() => println!("matches the node's prestateTracer diff"),
+                Some(m) => {
+                    println!("{} mismatch(es) with the node's prestateTracer diff", m.len());
+                    for mismatch in m {
+                        println!("  {}", mismatch);
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+    Ok(agrees)
+}
+
+#[derive(Serialize, Default)]
+struct TraceStats {
+    steps: u64,
+    max_depth: u64,
+    gas_start: u64,
+    gas_end: u64,
+    errors: u64,
+    unknown_opcodes: u64,
+    opcodes: BTreeMap<String, u64>,
+}
+
+impl TraceStats {
+    // one pass over the trace, memory stays bounded by a single step
+    fn collect(trace_path: &Path, client: ClientFlavor, strict: bool) -> Result<Self> {
+        let mut stats = TraceStats::default();
+        let mut reader = StructLogReader::open(trace_path)?.quirks(client.quirks()).strict(strict);
+        for step in reader.by_ref() {
+            let step = step?;
+            if stats.steps == 0 {
+                stats.gas_start = step.gas;
+            }
+            stats.steps += 1;
+            stats.gas_end = step.gas;
+            stats.max_depth = stats.max_depth.max(step.depth);
+            if step.error.is_some() {
+                stats.errors += 1;
+            }
+            *stats.opcodes.entry(step.opcode.to_string()).or_default() += 1;
+        }
+        stats.unknown_opcodes = reader.unknown_opcodes();
+        Ok(stats)
+    }
+
+    fn print(&self) {
+        println!("steps       {}", self.steps);
+        println!("max depth   {}", self.max_depth);
+        println!("gas         {} -> {}", self.gas_start, self.gas_end);
+        println!("errors      {}", self.errors);
+        if self.unknown_opcodes > 0 {
+            println!("unknown ops {}", self.unknown_opcodes);
+        }
+
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        println!("top opcodes");
+        for (name, count) in opcodes.into_iter().take(10) {
+            println!("  {:<14} {}", name, count);
+        }
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pc_range() {
+        assert_eq!(parse_pc_range("10..20").unwrap(), 10..20);
+        assert_eq!(parse_pc_range("0x0a..0x14").unwrap(), 10..20);
+        assert_eq!(parse_pc_range("7").unwrap(), 7..8);
+        assert_eq!(parse_pc_range(&u64::MAX.to_string()).unwrap_err().to_string(), format!("pc {} is past the last one", u64::MAX));
+        assert!(parse_pc_range("10..5").is_err());
+        assert!(parse_pc_range("10..10").is_err());
+        assert!(parse_pc_range("a..b").is_err());
+    }
+
+    #[test]
+    fn test_tree_builder_falls_back_to_receipt() {
+        let tmp = tempfile::tempdir().unwrap();
+        let trace_path = tmp.path().join("trace.json");
+        let node_down = || Err(anyhow::anyhow!("connection refused"));
+
+        // nothing to fall back to, the node's error stands
+        let err = tree_builder(node_down(), &trace_path).err().unwrap();
+        assert!(err.to_string().contains("connection refused"));
+
+        std::fs::write(tmp.path().join("receipt.json"), r#"{"jsonrpc":"2.0","id":"1","result":{
+            "from":"0x00000000000000000000000000000000000000aa","to":"0x00000000000000000000000000000000000000bb","logs":[]}}"#).unwrap();
+        assert!(tree_builder(node_down(), &trace_path).is_ok());
+    }
+}
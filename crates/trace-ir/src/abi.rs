@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use alloy_dyn_abi::{DynSolType, DynSolValue, EventExt, FunctionExt, JsonAbiExt};
+use alloy_json_abi::{Error, Event, Function, JsonAbi};
+use alloy_primitives::{Address, B256, Selector, U256};
+use anyhow::{Result, Context, anyhow};
+use serde::{Serialize, Deserialize};
+
+use crate::{CallFrame, CallType, Log, Word};
+
+const SIGNATURES: &str = include_str!("../data/signatures.txt");
+
+// Error(string) and Panic(uint256), what solidity itself reverts with
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+// a function call, event or custom error matched against a known signature
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decoded {
+    pub name: String,
+    pub signature: String,
+    pub params: Vec<Param>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Param {
+    pub name: String,           // empty when the signature does not name it
+    pub ty: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Revert {
+    Error(String),
+    Panic { code: Word, reason: String },
+    Custom(Decoded),
+    Raw(String),                // 0x hex of revert data nothing matched
+}
+
+// what the ABI registry could make of a frame, None fields fell back to raw hex
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameAbi {
+    pub function: Option<Decoded>,
+    pub output: Option<Vec<Param>>,
+    pub revert: Option<Revert>,
+}
+
+// name(value, value), how the tree renderer shows it
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values: Vec<&str> = self.params.iter().map(|p| p.value.as_str()).collect();
+        write!(f, "{}({})", self.name, values.join(", "))
+    }
+}
+
+impl fmt::Display for Revert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Revert::Error(message) => write!(f, "{:?}", message),
+            Revert::Panic { code, reason } => write!(f, "panic {:#04x}: {}", code, reason),
+            Revert::Custom(error) => write!(f, "{}", error),
+            Revert::Raw(hex) => f.write_str(hex),
+        }
+    }
+}
+
+// everything known under one selector or topic
+#[derive(Default)]
+struct Signatures {
+    functions: HashMap<Selector, Vec<Function>>,
+    events: HashMap<B256, Vec<Event>>,
+    errors: HashMap<Selector, Vec<Error>>,
+}
+
+impl Signatures {
+    fn add_abi(&mut self, abi: &JsonAbi) {
+        for function in abi.functions() {
+            self.functions.entry(function.selector()).or_default().push(function.clone());
+        }
+        for event in abi.events() {
+            self.events.entry(event.selector()).or_default().push(event.clone());
+        }
+        for error in abi.errors() {
+            self.errors.entry(error.selector()).or_default().push(error.clone());
+        }
+    }
+}
+
+// contract artifacts (foundry, hardhat) wrap the ABI, etherscan hands out the bare array
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AbiFile {
+    Bare(JsonAbi),
+    Artifact { abi: JsonAbi },
+}
+
+// Decodes calldata, return data, reverts and logs. Per-address ABIs win over the
+// bundled signature list, anything neither knows stays raw hex.
+pub struct AbiRegistry {
+    contracts: HashMap<Address, Signatures>,
+    bundled: Signatures,
+}
+
+impl Default for AbiRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AbiRegistry {
+    // with the bundled signatures only
+    pub fn new() -> Self {
+        let mut registry = Self { contracts: HashMap::new(), bundled: Signatures::default() };
+        for line in SIGNATURES.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            // the file is part of the crate, a bad line is a bug caught by the tests
+            let _ = registry.add_signature(line);
+        }
+        registry
+    }
+
+    // one `function ...`, `event ...` or `error ...` declaration, used for every address
+    pub fn add_signature(&mut self, declaration: &str) -> Result<()> {
+        let parse_error = |e| anyhow!("invalid signature {:?}: {}", declaration, e);
+        let signatures = &mut self.bundled;
+
+        if declaration.starts_with("event ") {
+            let event = Event::parse(declaration).map_err(parse_error)?;
+            signatures.events.entry(event.selector()).or_default().push(event);
+        } else if declaration.starts_with("error ") {
+            let error = Error::parse(declaration).map_err(parse_error)?;
+            signatures.errors.entry(error.selector()).or_default().push(error);
+        } else {
+            let function = Function::parse(declaration).map_err(parse_error)?;
+            signatures.functions.entry(function.selector()).or_default().push(function);
+        }
+        Ok(())
+    }
+
+    pub fn add_abi(&mut self, address: Address, abi: &JsonAbi) {
+        self.contracts.entry(address).or_default().add_abi(abi);
+    }
+
+    // <address>.json files, each a bare ABI array or a build artifact with an "abi" field
+    pub fn load_dir(mut self, dir: &Path) -> Result<Self> {
+        let entries = fs::read_dir(dir).with_context(|| format!("could not read ABI directory {:?}", dir))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Some(address) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<Address>().ok()) else {
+                continue;
+            };
+
+            let json = fs::read_to_string(&path).with_context(|| format!("could not read {:?}", path))?;
+            let abi = match serde_json::from_str(&json).with_context(|| format!("invalid ABI in {:?}", path))? {
+                AbiFile::Bare(abi) | AbiFile::Artifact { abi } => abi,
+            };
+            self.add_abi(address, &abi);
+        }
+        Ok(self)
+    }
+
+    // the contract's own ABI first, then the bundled list
+    fn lookup<'a, K, T>(&'a self, address: Address, key: &K, table: fn(&Signatures) -> &HashMap<K, Vec<T>>) -> impl Iterator<Item = &'a T>
+    where
+        K: std::hash::Hash + Eq + 'a,
+        T: 'a,
+    {
+        let own = self.contracts.get(&address).and_then(|s| table(s).get(key));
+        own.into_iter().chain(table(&self.bundled).get(key)).flatten()
+    }
+
+    fn function(&self, address: Address, calldata: &[u8]) -> Option<(&Function, Vec<DynSolValue>)> {
+        let selector = Selector::try_from(calldata.get(..4)?).ok()?;
+        self.lookup(address, &selector, |s| &s.functions)
+            .find_map(|f| f.abi_decode_input(&calldata[4..], true).ok().map(|values| (f, values)))
+    }
+
+    pub fn decode_call(&self, address: Address, calldata: &[u8]) -> Option<Decoded> {
+        let (function, values) = self.function(address, calldata)?;
+        Some(decoded(&function.name, function.signature(), function.inputs.iter().map(|p| (&p.name, &p.ty)), &values))
+    }
+
+    pub fn decode_output(&self, address: Address, calldata: &[u8], return_data: &[u8]) -> Option<Vec<Param>> {
+        let (function, _) = self.function(address, calldata)?;
+        if function.outputs.is_empty() {
+            return None;
+        }
+        let values = function.abi_decode_output(return_data, true).ok()?;
+        Some(params(function.outputs.iter().map(|p| (&p.name, &p.ty)), &values))
+    }
+
+    pub fn decode_revert(&self, address: Address, data: &[u8]) -> Revert {
+        let raw = || Revert::Raw(format!("0x{}", hex::encode(data)));
+        let Some(selector) = data.get(..4) else {
+            return raw();
+        };
+        let body = &data[4..];
+
+        if selector == ERROR_SELECTOR {
+            if let Ok(DynSolValue::String(message)) = DynSolType::String.abi_decode(body) {
+                return Revert::Error(message);
+            }
+        } else if selector == PANIC_SELECTOR {
+            if let Ok(DynSolValue::Uint(code, _)) = DynSolType::Uint(256).abi_decode(body) {
+                return Revert::Panic { code: Word::from(code), reason: panic_reason(code).to_string() };
+            }
+        } else if let Ok(selector) = Selector::try_from(selector) {
+            let custom = self.lookup(address, &selector, |s| &s.errors)
+                .find_map(|e| e.abi_decode_input(body, true).ok().map(|values| (e, values)));
+            if let Some((error, values)) = custom {
+                return Revert::Custom(decoded(&error.name, error.signature(), error.inputs.iter().map(|p| (&p.name, &p.ty)), &values));
+            }
+        }
+        raw()
+    }
+
+    // `code_address` is where the emitting code lives, under DELEGATECALL that is not log.address
+    pub fn decode_log(&self, log: &Log, code_address: Address) -> Option<Decoded> {
+        let topic = log.topics.first()?;
+        let candidates = self.lookup(log.address, topic, |s| &s.events)
+            .chain(self.lookup(code_address, topic, |s| &s.events));
+
+        candidates.filter(|e| !e.anonymous).find_map(|event| {
+            let decoded_event = event.decode_log_parts(log.topics.iter().copied(), &log.data, true).ok()?;
+            // the indexed count has to match exactly, ERC-20 and ERC-721 Transfer share a topic
+            if decoded_event.indexed.len() + 1 != log.topics.len() {
+                return None;
+            }
+
+            // back into declaration order
+            let (mut indexed, mut body) = (decoded_event.indexed.into_iter(), decoded_event.body.into_iter());
+            let values: Vec<DynSolValue> = event.inputs.iter()
+                .map(|p| if p.indexed { indexed.next() } else { body.next() })
+                .collect::<Option<_>>()?;
+            Some(decoded(&event.name, event.signature(), event.inputs.iter().map(|p| (&p.name, &p.ty)), &values))
+        })
+    }
+
+    // fills in FrameAbi for every frame and `decoded` for every log in the tree
+    pub fn annotate(&self, frame: &mut CallFrame) {
+        let creates = matches!(frame.call_type, CallType::Create | CallType::Create2 | CallType::EofCreate);
+        // initcode is not calldata, and a frame that ran no code returned nothing
+        if !creates {
+            let mut abi = FrameAbi {
+                function: self.decode_call(frame.to, &frame.calldata),
+                ..FrameAbi::default()
+            };
+            if frame.success && !frame.return_data.is_empty() {
+                abi.output = self.decode_output(frame.to, &frame.calldata, &frame.return_data);
+            }
+            if !frame.success && !frame.return_data.is_empty() {
+                abi.revert = Some(self.decode_revert(frame.to, &frame.return_data));
+            }
+            frame.abi = Some(abi);
+        } else if !frame.success && !frame.return_data.is_empty() {
+            frame.abi = Some(FrameAbi {
+                revert: Some(self.decode_revert(frame.to, &frame.return_data)),
+                ..FrameAbi::default()
+            });
+        }
+
+        let code_address = frame.to;
+        for log in &mut frame.logs {
+            log.decoded = self.decode_log(log, code_address);
+        }
+        for child in &mut frame.children {
+            self.annotate(child);
+        }
+    }
+}
+
+fn decoded<'a>(name: &str, signature: String, inputs: impl Iterator<Item = (&'a String, &'a String)>, values: &[DynSolValue]) -> Decoded {
+    Decoded { name: name.to_string(), signature, params: params(inputs, values) }
+}
+
+fn params<'a>(inputs: impl Iterator<Item = (&'a String, &'a String)>, values: &[DynSolValue]) -> Vec<Param> {
+    inputs
+        .zip(values)
+        .map(|((name, ty), value)| Param { name: name.clone(), ty: ty.clone(), value: format_value(value) })
+        .collect()
+}
+
+// solidity literal style: checksummed addresses, 0x hex bytes, quoted strings
+pub fn format_value(value: &DynSolValue) -> String {
+    let list = |values: &[DynSolValue]| values.iter().map(format_value).collect::<Vec<_>>().join(", ");
+    match value {
+        DynSolValue::Bool(b) => b.to_string(),
+        DynSolValue::Int(i, _) => i.to_string(),
+        DynSolValue::Uint(u, _) => u.to_string(),
+        DynSolValue::FixedBytes(word, size) => format!("0x{}", hex::encode(&word[..*size])),
+        DynSolValue::Address(address) => address.to_checksum(None),
+        DynSolValue::Function(function) => format!("0x{}", hex::encode(function)),
+        DynSolValue::Bytes(bytes) => format!("0x{}", hex::encode(bytes)),
+        DynSolValue::String(s) => format!("{:?}", s),
+        DynSolValue::Array(values) | DynSolValue::FixedArray(values) => format!("[{}]", list(values)),
+        DynSolValue::Tuple(values) => format!("({})", list(values)),
+    }
+}
+
+// https://docs.soliditylang.org/en/latest/control-structures.html#panic-via-assert-and-error-via-require
+fn panic_reason(code: U256) -> &'static str {
+    match u64::try_from(code).unwrap_or(u64::MAX) {
+        0x00 => "generic panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "corrupted storage byte array",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized function",
+        _ => "unknown panic code",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::keccak256;
+
+    fn word(bytes: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; 32 - bytes.len()];
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    #[test]
+    fn test_bundled_signatures_parse() {
+        let lines = SIGNATURES.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'));
+        let mut registry = AbiRegistry { contracts: HashMap::new(), bundled: Signatures::default() };
+        for line in lines {
+            registry.add_signature(line).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_decodes_call_and_revert() {
+        let registry = AbiRegistry::new();
+        let token = Address::with_last_byte(0xbb);
+
+        // transfer(0x..cc, 5)
+        let mut calldata = keccak256("transfer(address,uint256)")[..4].to_vec();
+        calldata.extend(word(&[0xcc]));
+        calldata.extend(word(&[5]));
+        let call = registry.decode_call(token, &calldata).unwrap();
+        assert_eq!(call.name, "transfer");
+        assert_eq!(call.params[0].name, "to");
+        assert_eq!(call.params[1].value, "5");
+        assert_eq!(registry.decode_output(token, &calldata, &word(&[1])), None);
+
+        // Error("nope")
+        let mut revert = ERROR_SELECTOR.to_vec();
+        revert.extend(word(&[0x20]));
+        revert.extend(word(&[4]));
+        revert.extend(b"nope".iter().chain([0u8; 28].iter()));
+        assert_eq!(registry.decode_revert(token, &revert), Revert::Error("nope".to_string()));
+
+        let mut panic = PANIC_SELECTOR.to_vec();
+        panic.extend(word(&[0x11]));
+        assert_eq!(registry.decode_revert(token, &panic).to_string(), "panic 0x11: arithmetic overflow or underflow");
+
+        assert_eq!(registry.decode_revert(token, &[0xde, 0xad]), Revert::Raw("0xdead".to_string()));
+        assert_eq!(registry.decode_call(token, &[0xde, 0xad, 0xbe, 0xef]), None);
+    }
+
+    #[test]
+    fn test_decodes_logs_by_indexed_count() {
+        let registry = AbiRegistry::new();
+        let topic = |b: u8| B256::from_slice(&word(&[b]));
+        let transfer = keccak256("Transfer(address,address,uint256)");
+
+        let erc20 = Log {
+            address: Address::with_last_byte(0xbb),
+            topics: vec![transfer, topic(0xaa), topic(0xcc)],
+            data: word(&[7]),
+            position: 0,
+            discarded: false,
+            decoded: None,
+        };
+        let decoded = registry.decode_log(&erc20, erc20.address).unwrap();
+        assert_eq!(decoded.params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), ["from", "to", "value"]);
+        assert_eq!(decoded.params[2].value, "7");
+
+        let erc721 = Log { topics: vec![transfer, topic(0xaa), topic(0xcc), topic(9)], data: Vec::new(), ..erc20 };
+        let decoded = registry.decode_log(&erc721, erc721.address).unwrap();
+        assert_eq!(decoded.params[2].name, "tokenId");
+    }
+
+    #[test]
+    fn test_contract_abi_wins() {
+        let abi: JsonAbi = serde_json::from_str(r#"[
+            {"type":"function","name":"transfer","stateMutability":"nonpayable",
+             "inputs":[{"name":"recipient","type":"address"},{"name":"wad","type":"uint256"}],
+             "outputs":[{"name":"ok","type":"bool"}]}
+        ]"#).unwrap();
+        let mut registry = AbiRegistry::new();
+        let token = Address::with_last_byte(0xbb);
+        registry.add_abi(token, &abi);
+
+        let mut calldata = keccak256("transfer(address,uint256)")[..4].to_vec();
+        calldata.extend(word(&[0xcc]));
+        calldata.extend(word(&[5]));
+        assert_eq!(registry.decode_call(token, &calldata).unwrap().params[0].name, "recipient");
+        assert_eq!(registry.decode_output(token, &calldata, &word(&[1])).unwrap()[0].value, "true");
+        // other addresses still get the bundled names
+        assert_eq!(registry.decode_call(Address::ZERO, &calldata).unwrap().params[0].name, "to");
+    }
+}
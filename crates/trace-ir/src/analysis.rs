@@ -1,4 +1,5 @@
-use crate::{Opcode, Word, Instruction, CallFrame, CallType};
+use std::collections::HashMap;
+use crate::{Opcode, Word, Instruction, CallFrame, CallType, FrameError, Log};
 use alloy_primitives::Address;
 use anyhow::{Result, anyhow};
 
@@ -6,81 +7,934 @@ pub struct TraceAnalyzer;
 
 impl TraceAnalyzer {
 
-    pub fn build_call_tree(instructions: Vec<Instruction>) -> Result<CallFrame>{
-        if instructions.is_empty() {
-            return Err(anyhow!("Trace is empty!"))
+    pub fn build_call_tree<I>(instructions: I) -> Result<CallFrame>
+    where
+        I: IntoIterator<Item = Instruction>,
+    {
+        let mut builder = CallTreeBuilder::new();
+        for instr in instructions {
+            builder.push(instr)?;
         }
+        builder.finish()
+    }
+}
 
-        let root_frame = CallFrame::new(
-            CallType::Root,
-            Address::ZERO,
-            Address::ZERO,
-            instructions[0].gas
-        );
+// Push-style call tree reconstruction, fed one step at a time (e.g. from StructLogReader).
+// Instructions are moved into their frame, never cloned, and carry the canonical
+// 1-based depth, client depth bases are taken care of by the reader's Quirks.
+pub struct CallTreeBuilder {
+    frame_stack: Vec<CallFrame>,
 
-        let mut frame_stack: Vec<CallFrame> = Vec::new();
-        frame_stack.push(root_frame);
+    // when false no frame gets an instruction list, only the step before
+    // the current one is kept around to classify new frames
+    store_instructions: bool,
+    last_step: Option<Instruction>,
 
-        let mut previous_depth = instructions[0].depth;
+    // refund counter when each open frame was entered
+    entry_refunds: Vec<u64>,
 
-        for instr in instructions {
-            let current_depth = instr.depth;
-
-            if current_depth > previous_depth {
-                let parent = frame_stack.last_mut().expect("Stack Underflow!!");
-                let triggering_op = parent.instructions.last().map(|i| i.opcode).unwrap_or(Opcode::INVALID);
-
-                let calltype: CallType = match triggering_op {
-                    Opcode::CALL => CallType::Call,
-                    Opcode::CALLCODE => CallType::CallCode,
-                    Opcode::DELEGATECALL => CallType::DelegateCall,
-                    Opcode::STATICCALL => CallType::StaticCall,
-                    Opcode::CREATE => CallType::Create,
-                    Opcode::CREATE2 => CallType::Create2,
-                    _ => CallType::Call,
-                };
+    // storage owner and undo log of each open frame
+    frame_storage: Vec<FrameStorage>,
+    // last known value of every slot seen so far
+    slots: HashMap<(StorageOwner, Word), Word>,
+    // SLOAD without a storage map, its value is on top of the next step's stack
+    pending_read: Option<(StorageOwner, Address, Word)>,
+    next_create: u64,
+    logs_emitted: u64,
 
-                let new_frame = CallFrame::new(
-                    calltype,
-                    Address::ZERO,
-                    Address::ZERO,
-                    instr.gas
-                );
+    // the trace does not carry the tx itself, the root frame is seeded from these
+    tx_from: Address,
+    tx_to: Address,
+    tx_value: Word,
+    tx_input: Vec<u8>,
+}
 
-                frame_stack.push(new_frame);
+impl Default for CallTreeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            }else if previous_depth < current_depth {
+impl CallTreeBuilder {
+    pub fn new() -> Self {
+        Self {
+            frame_stack: Vec::new(),
+            store_instructions: true,
+            last_step: None,
+            entry_refunds: Vec::new(),
+            frame_storage: Vec::new(),
+            slots: HashMap::new(),
+            pending_read: None,
+            next_create: 0,
+            logs_emitted: 0,
+            tx_from: Address::ZERO,
+            tx_to: Address::ZERO,
+            tx_value: Word::ZERO,
+            tx_input: Vec::new(),
+        }
+    }
 
-                // for geth client depth starts from 1, for erigon starts from 0 
-                while frame_stack.len() as u64 > current_depth {
-                    if let Some(finished_frame) = frame_stack.pop(){
-                        if let Some(parent) = frame_stack.last_mut(){
-                            parent.children.push(finished_frame);
-                        }
-                    }
-                }
+    // sender, recipient, value and input of the traced tx (e.g. from the receipt),
+    // without it the root frame and the 'from' of its direct children stay zero
+    pub fn with_transaction(mut self, from: Address, to: Address, value: Word, input: Vec<u8>) -> Self {
+        self.tx_from = from;
+        self.tx_to = to;
+        self.tx_value = value;
+        self.tx_input = input;
+        self
+    }
+
+    // false builds every frame without its instruction list, not even open frames keep
+    // their steps, so memory is bounded by call depth instead of trace length
+    pub fn store_instructions(mut self, store: bool) -> Self {
+        self.store_instructions = store;
+        self
+    }
+
+    // the step executed right before the one being pushed
+    fn previous_step(&self) -> Option<&Instruction> {
+        if self.store_instructions {
+            self.frame_stack.last().and_then(|f| f.instructions.last())
+        } else {
+            self.last_step.as_ref()
+        }
+    }
+
+    // current call depth in the canonical convention, the root frame is depth 1
+    pub fn depth(&self) -> u64 {
+        self.frame_stack.len() as u64
+    }
+
+    pub fn push(&mut self, instr: Instruction) -> Result<()> {
+        let current_depth = instr.depth;
+
+        if self.frame_stack.is_empty() {
+            if current_depth != 1 {
+                return Err(anyhow!("trace starts at depth {} instead of 1, is the client flavor right?", current_depth));
             }
 
-            if let Some(current_frame) = frame_stack.last_mut() {
-                if instr.opcode == Opcode::REVERT {
-                    current_frame.success = false;
-                    current_frame.error = Some("Reverted".to_string());
+            let mut root = CallFrame::new(
+                CallType::Root,
+                self.tx_from,
+                self.tx_to,
+                instr.gas
+            );
+            root.value = self.tx_value;
+            root.calldata = std::mem::take(&mut self.tx_input);
+
+            self.frame_storage.push(FrameStorage::new(StorageOwner::Account(root.storage_address)));
+            self.frame_stack.push(root);
+            self.entry_refunds.push(instr.refund.unwrap_or(0));
+        }
+
+        let previous_depth = self.depth();
+
+        if let Some((owner, address, slot)) = self.pending_read.take()
+            && current_depth == previous_depth
+            && let Some(value) = instr.stack_top(0)
+            && let Some(frame) = self.frame_stack.last_mut()
+        {
+            // same as the geth branch, a later SSTORE needs the read value as its original
+            self.slots.insert((owner, slot), *value);
+            frame.storage.record_read(address, slot, *value);
+        }
+
+        if current_depth > previous_depth {
+            if current_depth != previous_depth + 1 {
+                return Err(anyhow!("depth jumped from {} to {} at pc {}", previous_depth, current_depth, instr.pc));
+            }
+
+            let parent = self.frame_stack.last().ok_or_else(|| anyhow!("Stack Underflow!!"))?;
+            let new_frame = open_frame(parent, self.previous_step(), instr.gas);
+
+            let owner = match new_frame.call_type {
+                CallType::DelegateCall | CallType::ExtDelegateCall | CallType::CallCode => {
+                    self.frame_storage.last().map(|f| f.owner).unwrap_or(StorageOwner::Account(new_frame.storage_address))
+                }
+                // CREATE2's address is known upfront, the others only once they return
+                CallType::Create | CallType::EofCreate => {
+                    self.next_create += 1;
+                    StorageOwner::Created(self.next_create)
                 }
+                _ => StorageOwner::Account(new_frame.storage_address),
+            };
+            self.frame_storage.push(FrameStorage::new(owner));
+            self.frame_stack.push(new_frame);
+            self.entry_refunds.push(instr.refund.unwrap_or(0));
+
+        } else if current_depth < previous_depth {
+            // frames return one at a time, a bigger drop would leave the frames in between
+            // without a last step to read their result from
+            if current_depth + 1 != previous_depth {
+                return Err(anyhow!("depth dropped from {} to {} at pc {}", previous_depth, current_depth, instr.pc));
+            }
+
+            // the innermost frame returned
+            let last_op = self.previous_step().map(|i| i.opcode);
+            self.finalize_frame();
+            let closed = self.close_frame()?;
+
+            if let Some(parent) = self.frame_stack.last_mut()
+                && let Some(child) = parent.children.last_mut()
+            {
+                apply_call_result(child, &instr, last_op);
+            }
+            // only now is it known whether the callee's writes stick
+            self.settle_storage(closed);
+
+        } else if let Some(call) = self.previous_step().filter(|s| s.info().is_call) {
+            // same depth right after a call: the callee had no code to run (EOA, precompile)
+            // or the call failed before entering it
+            let parent = self.frame_stack.last().ok_or_else(|| anyhow!("Stack Underflow!!"))?;
+            let mut frame = open_frame(parent, Some(call), 0);
+            frame.gas_limit = forwarded_gas(call);
+            frame.gas_used = frame.gas_limit.saturating_sub(returned_gas(call, &instr));
+
+            let depth = self.depth();
+            apply_call_result(&mut frame, &instr, None);
+            if !frame.success && depth > MAX_CALL_DEPTH {
+                frame.error = Some(FrameError::CallDepthExceeded);
+            }
+
+            let parent = self.frame_stack.last_mut().ok_or_else(|| anyhow!("Stack Underflow!!"))?;
+            parent.children.push(frame);
+        }
+
+        self.track_storage(&instr);
+        self.record_log(&instr);
+
+        let current_frame = self.frame_stack.last_mut().ok_or_else(|| anyhow!("Stack Underflow!!"))?;
+        current_frame.step_count += 1;
+        if self.store_instructions {
+            current_frame.instructions.push(instr);
+        } else {
+            self.last_step = Some(instr);
+        }
+
+        Ok(())
+    }
+
+    // reads everything the innermost frame's last step says about how it ended:
+    // return data, gas left, refunds and whether it halted exceptionally
+    fn finalize_frame(&mut self) {
+        let Some(step) = self.previous_step() else {
+            return;
+        };
+
+        // RETURN/REVERT hand memory[offset..offset+size] back to the caller
+        let return_data = match step.opcode {
+            Opcode::RETURN | Opcode::REVERT => match (step.stack_top(0), step.stack_top(1)) {
+                (Some(offset), Some(size)) => step.memory_slice(offset, size),
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+
+        let halt = halt_reason(step);
+        let beneficiary = match step.opcode {
+            Opcode::SELFDESTRUCT if halt.is_none() => step.stack_top(0).map(Word::to_address),
+            _ => None,
+        };
 
-                current_frame.instructions.push(instr.clone());
+        // exceptional halts burn everything, REVERT hands the remainder back
+        let gas_left = match halt {
+            None | Some(FrameError::Reverted) => step.gas.saturating_sub(step.gas_cost.unwrap_or(0)),
+            Some(_) => 0,
+        };
+        let exit_refund = step.refund.unwrap_or(0);
+        let entry_refund = self.entry_refunds.last().copied().unwrap_or(0);
+
+        if let Some(frame) = self.frame_stack.last_mut() {
+            frame.return_data = return_data;
+            frame.selfdestruct = beneficiary;
+            frame.gas_used = frame.gas_limit.saturating_sub(gas_left);
+            if let Some(error) = halt {
+                frame.success = false;
+                frame.error = Some(error);
+            } else {
+                // two's complement difference, SSTORE can take refunds back inside a frame
+                frame.gas_refund = exit_refund.wrapping_sub(entry_refund) as i64;
             }
+        }
+    }
+
+    // records what an SLOAD read or an SSTORE wrote in the current frame
+    fn track_storage(&mut self, step: &Instruction) {
+        let (Some(frame), Some(state)) = (self.frame_stack.last_mut(), self.frame_storage.last_mut()) else {
+            return;
+        };
+        let Some(slot) = step.stack_top(0).copied() else {
+            return;
+        };
+        let address = frame.storage_address;
 
-            previous_depth = current_depth;
+        match step.opcode {
+            // geth puts the loaded value in the step's storage map, other clients only
+            // show it as the result on the next step
+            Opcode::SLOAD => match step.storage.as_ref().and_then(|s| s.get(&slot)) {
+                Some(value) => {
+                    self.slots.insert((state.owner, slot), *value);
+                    frame.storage.record_read(address, slot, *value);
+                }
+                None => self.pending_read = Some((state.owner, address, slot)),
+            },
+            Opcode::SSTORE if step.error.is_none() => {
+                let Some(value) = step.stack_top(1).copied() else {
+                    return;
+                };
+                let original = self.slots.insert((state.owner, slot), value);
+                state.journal.push((state.owner, slot, original));
+                frame.storage.record_write(address, slot, original, value);
+            }
+            _ => {}
         }
+    }
+
+    // LOGn pops offset, size and n topics, the data comes from memory
+    fn record_log(&mut self, step: &Instruction) {
+        let topic_count = match step.opcode {
+            Opcode::LOG0 => 0,
+            Opcode::LOG1 => 1,
+            Opcode::LOG2 => 2,
+            Opcode::LOG3 => 3,
+            Opcode::LOG4 => 4,
+            _ => return,
+        };
+        if step.error.is_some() || step.stack.len() < 2 + topic_count {
+            return;
+        }
+        let Some(frame) = self.frame_stack.last_mut() else {
+            return;
+        };
+
+        let arg = |n: usize| step.stack_top(n).copied().unwrap_or(Word::ZERO);
+        frame.logs.push(Log {
+            address: frame.storage_address,
+            topics: (0..topic_count).map(|i| arg(2 + i).to_b256()).collect(),
+            data: step.memory_slice(&arg(0), &arg(1)),
+            position: self.logs_emitted,
+            discarded: false,
+            decoded: None,
+        });
+        self.logs_emitted += 1;
+    }
+
+    // folds the storage effects of the frame just closed into its parent once its result
+    // is final. a failed frame keeps its reads but all writes under it are undone
+    fn settle_storage(&mut self, closed: FrameStorage) {
+        let (Some(parent), Some(parent_state)) = (self.frame_stack.last_mut(), self.frame_storage.last_mut()) else {
+            return;
+        };
+        let Some(child) = parent.children.last_mut() else {
+            return;
+        };
 
-        while frame_stack.len() > 1 {
-            if let Some(finished_frame) = frame_stack.pop(){
-                if let Some(parent) = frame_stack.last_mut(){
-                    parent.children.push(finished_frame);
+        if child.success {
+            let mut journal = closed.journal;
+            // a finished create owns the slots it wrote under its placeholder
+            if let StorageOwner::Created(_) = closed.owner {
+                let resolved = StorageOwner::Account(child.storage_address);
+                let keys: Vec<_> = self.slots.keys().filter(|(owner, _)| *owner == closed.owner).copied().collect();
+                for key in keys {
+                    if let Some(value) = self.slots.remove(&key) {
+                        self.slots.insert((resolved, key.1), value);
+                    }
+                }
+                for entry in journal.iter_mut().filter(|e| e.0 == closed.owner) {
+                    entry.0 = resolved;
                 }
             }
+            parent.storage.merge(&child.storage, true);
+            parent_state.journal.extend(journal);
+        } else {
+            for (owner, slot, original) in closed.journal.into_iter().rev() {
+                match original {
+                    Some(value) => self.slots.insert((owner, slot), value),
+                    None => self.slots.remove(&(owner, slot)),
+                };
+            }
+            roll_back(child);
+            parent.storage.merge(&child.storage, false);
+        }
+    }
+
+    // pops the innermost frame and attaches it to its parent
+    fn close_frame(&mut self) -> Result<FrameStorage> {
+        if self.frame_stack.len() < 2 {
+            return Err(anyhow!("Stack Underflow!!"));
         }
 
-        frame_stack.pop().ok_or_else(|| anyhow!("Stack corrupted during reconstruction!!"))
+        let finished_frame = self.frame_stack.pop().ok_or_else(|| anyhow!("Stack Underflow!!"))?;
+        self.entry_refunds.pop();
+        let storage = self.frame_storage.pop().ok_or_else(|| anyhow!("Stack Underflow!!"))?;
+        let parent = self.frame_stack.last_mut().ok_or_else(|| anyhow!("Stack Underflow!!"))?;
+        parent.children.push(finished_frame);
+
+        Ok(storage)
+    }
+
+    pub fn finish(mut self) -> Result<CallFrame> {
+        if self.frame_stack.is_empty() {
+            return Err(anyhow!("Trace is empty!"))
+        }
+
+        self.finalize_frame();
+
+        while self.frame_stack.len() > 1 {
+            let closed = self.close_frame()?;
+            self.settle_storage(closed);
+        }
+
+        let mut root = self.frame_stack.pop().ok_or_else(|| anyhow!("Stack corrupted during reconstruction!!"))?;
+        if !root.success {
+            roll_back(&mut root);
+        }
+        Ok(root)
+    }
+}
+
+// whose slots an open frame touches. a CREATE/EOFCREATE frame writes to an account
+// whose address is only known once it returns, so it gets a placeholder until then
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum StorageOwner {
+    Account(Address),
+    Created(u64),
+}
+
+struct FrameStorage {
+    owner: StorageOwner,
+    // (owner, slot, value before the write) for every SSTORE this frame and its
+    // successful callees made, replayed backwards when the frame fails
+    journal: Vec<(StorageOwner, Word, Option<Word>)>,
+}
+
+impl FrameStorage {
+    fn new(owner: StorageOwner) -> Self {
+        Self { owner, journal: Vec::new() }
+    }
+}
+
+// a failed frame's writes and logs never happened, neither did those of anything it called
+fn roll_back(frame: &mut CallFrame) {
+    frame.storage.writes.clear();
+    for log in &mut frame.logs {
+        log.discarded = true;
+    }
+    for child in &mut frame.children {
+        roll_back(child);
+    }
+}
+
+// EVM call depth limit, a call made from deeper than this fails without running.
+// depths are 1-based, so a call from depth 1024 still enters depth 1025
+const MAX_CALL_DEPTH: u64 = 1024;
+
+// gas stipend added to value transferring calls
+const CALL_STIPEND: u64 = 2300;
+
+// why the frame ended on this step, None for a normal STOP/RETURN/SELFDESTRUCT
+fn halt_reason(step: &Instruction) -> Option<FrameError> {
+    if let Some(msg) = &step.error {
+        return Some(FrameError::from_message(msg));
+    }
+
+    let info = step.info();
+    match step.opcode {
+        Opcode::STOP | Opcode::RETURN | Opcode::RETURNCONTRACT | Opcode::SELFDESTRUCT => None,
+        Opcode::REVERT => Some(FrameError::Reverted),
+        Opcode::INVALID | Opcode::Unknown(_) | Opcode::UnknownName(_) | Opcode::UnknownNameOverflow => {
+            Some(FrameError::InvalidOpcode)
+        }
+
+        // the frame ended on a step that does not halt, work out what went wrong
+        _ if step.gas_cost.is_some_and(|cost| cost > step.gas) => Some(FrameError::OutOfGas),
+        _ if step.stack.len() < info.inputs as usize => Some(FrameError::StackUnderflow),
+        _ if step.stack.len() - info.inputs as usize + info.outputs as usize > 1024 => Some(FrameError::StackOverflow),
+        Opcode::JUMP | Opcode::JUMPI => Some(FrameError::InvalidJump),
+        _ => Some(FrameError::Other(format!("halted at {}", info.name))),
+    }
+}
+
+// EIP-150: a call can forward at most all but one 64th of the gas left
+fn all_but_one_64th(gas: u64) -> u64 {
+    gas - gas / 64
+}
+
+// gas handed to a callee that never produced a step of its own.
+// the static part of the call cost is not visible in the trace, so this is an upper bound
+// unless the requested amount is the binding limit
+fn forwarded_gas(call: &Instruction) -> u64 {
+    let available = all_but_one_64th(call.gas);
+
+    match call.opcode {
+        // EOF calls take no gas operand and pay no stipend
+        Opcode::CREATE | Opcode::CREATE2 | Opcode::EOFCREATE
+        | Opcode::EXTCALL | Opcode::EXTDELEGATECALL | Opcode::EXTSTATICCALL => available,
+        _ => {
+            let requested = call.stack_top(0).map(Word::saturating_to_u64).unwrap_or(0);
+            let stipend = match call.opcode {
+                Opcode::CALL | Opcode::CALLCODE if call.stack_top(2).is_some_and(|v| *v != Word::ZERO) => CALL_STIPEND,
+                _ => 0,
+            };
+            requested.min(available) + stipend
+        }
+    }
+}
+
+// gas the callee gave back, geth charges the forwarded gas as part of the call's gasCost
+fn returned_gas(call: &Instruction, resumed: &Instruction) -> u64 {
+    let after_call = call.gas.saturating_sub(call.gas_cost.unwrap_or(0));
+    resumed.gas.saturating_sub(after_call)
+}
+
+// the step resuming the caller has the call's result on top of its stack:
+// 1/0 for the CALL family, the new address or 0 for CREATE/CREATE2/EOFCREATE,
+// and 0 (success), 1 (revert) or 2 (failure) for the EXT*CALL family.
+// last_op is the callee's final opcode, None if it never ran any code
+fn apply_call_result(child: &mut CallFrame, resumed: &Instruction, last_op: Option<Opcode>) {
+    let Some(result) = resumed.stack_top(0) else {
+        return;
+    };
+
+    let failed = match child.call_type {
+        CallType::ExtCall | CallType::ExtDelegateCall | CallType::ExtStaticCall => *result != Word::ZERO,
+        _ => *result == Word::ZERO,
+    };
+
+    if failed {
+        child.success = false;
+        child.gas_refund = 0;
+        if child.error.is_none() {
+            // the callee ended normally but the caller saw a failure
+            child.error = Some(match (&child.call_type, last_op) {
+                (CallType::Create | CallType::Create2, Some(Opcode::RETURN))
+                | (CallType::EofCreate, Some(Opcode::RETURNCONTRACT)) => FrameError::CodeDepositFailed,
+                (_, None) if child.value != Word::ZERO => FrameError::InsufficientBalance,
+                _ => FrameError::Other("call failed".to_string()),
+            });
+        }
+        return;
+    }
+
+    child.success = true;
+    child.error = None;
+
+    if matches!(child.call_type, CallType::Create | CallType::Create2 | CallType::EofCreate) {
+        resolve_created_address(child, result.to_address());
+    }
+}
+
+// builds a frame from the operands of the call-like step that spawned it
+fn open_frame(parent: &CallFrame, trigger: Option<&Instruction>, gas: u64) -> CallFrame {
+    let triggering_op = trigger.map(|i| i.opcode).unwrap_or(Opcode::INVALID);
+
+    let calltype: CallType = match triggering_op {
+        Opcode::CALL => CallType::Call,
+        Opcode::CALLCODE => CallType::CallCode,
+        Opcode::DELEGATECALL => CallType::DelegateCall,
+        Opcode::STATICCALL => CallType::StaticCall,
+        Opcode::CREATE => CallType::Create,
+        Opcode::CREATE2 => CallType::Create2,
+        Opcode::EXTCALL => CallType::ExtCall,
+        Opcode::EXTDELEGATECALL => CallType::ExtDelegateCall,
+        Opcode::EXTSTATICCALL => CallType::ExtStaticCall,
+        Opcode::EOFCREATE => CallType::EofCreate,
+        _ => CallType::Call,
+    };
+
+    // the caller is whoever's context issued the call, not the code that ran it
+    let mut frame = CallFrame::new(calltype, parent.storage_address, Address::ZERO, gas);
+
+    let Some(step) = trigger.filter(|s| s.info().is_call) else {
+        return frame;
+    };
+    let arg = |n: usize| step.stack_top(n).copied().unwrap_or(Word::ZERO);
+
+    match frame.call_type {
+        // gas, addr, value, argsOffset, argsSize, retOffset, retSize
+        CallType::Call | CallType::CallCode => {
+            frame.to = arg(1).to_address();
+            frame.value = arg(2);
+            frame.calldata = step.memory_slice(&arg(3), &arg(4));
+        }
+        // gas, addr, argsOffset, argsSize, retOffset, retSize
+        CallType::DelegateCall | CallType::StaticCall => {
+            frame.to = arg(1).to_address();
+            frame.calldata = step.memory_slice(&arg(2), &arg(3));
+            if frame.call_type == CallType::DelegateCall {
+                // CALLVALUE is inherited from the caller
+                frame.value = parent.value;
+            }
+        }
+        // value, offset, size (, salt)
+        CallType::Create | CallType::Create2 => {
+            frame.value = arg(0);
+            frame.calldata = step.memory_slice(&arg(1), &arg(2));
+            if frame.call_type == CallType::Create2 {
+                let salt = arg(3).to_b256();
+                frame.to = parent.storage_address.create2_from_code(salt, &frame.calldata);
+            }
+            // CREATE depends on the sender's nonce, resolved once the frame returns
+        }
+        // target, argsOffset, argsSize, value
+        CallType::ExtCall => {
+            frame.to = arg(0).to_address();
+            frame.calldata = step.memory_slice(&arg(1), &arg(2));
+            frame.value = arg(3);
+        }
+        // target, argsOffset, argsSize
+        CallType::ExtDelegateCall | CallType::ExtStaticCall => {
+            frame.to = arg(0).to_address();
+            frame.calldata = step.memory_slice(&arg(1), &arg(2));
+            if frame.call_type == CallType::ExtDelegateCall {
+                frame.value = parent.value;
+            }
+        }
+        // value, salt, inputOffset, inputSize. the initcode is a subcontainer, not in memory,
+        // so the address is only known from the result
+        CallType::EofCreate => {
+            frame.value = arg(0);
+            frame.calldata = step.memory_slice(&arg(2), &arg(3));
+        }
+        CallType::Root => {}
+    }
+
+    frame.storage_address = match frame.call_type {
+        CallType::DelegateCall | CallType::ExtDelegateCall | CallType::CallCode => parent.storage_address,
+        _ => frame.to,
+    };
+
+    frame
+}
+
+// fixes up a create frame, and every descendant that ran in its context, once the address is known
+fn resolve_created_address(frame: &mut CallFrame, address: Address) {
+    let placeholder = frame.storage_address;
+    if placeholder == address {
+        return;
+    }
+
+    frame.to = address;
+    frame.storage_address = address;
+    frame.storage.rebind(placeholder, address);
+    rebind_logs(&mut frame.logs, placeholder, address);
+    rebind_context(&mut frame.children, placeholder, address);
+}
+
+fn rebind_context(children: &mut [CallFrame], old: Address, new: Address) {
+    for child in children {
+        if child.from == old {
+            child.from = new;
+        }
+        if matches!(child.call_type, CallType::DelegateCall | CallType::ExtDelegateCall | CallType::CallCode)
+            && child.storage_address == old
+        {
+            child.storage_address = new;
+            child.storage.rebind(old, new);
+            rebind_logs(&mut child.logs, old, new);
+            rebind_context(&mut child.children, old, new);
+        }
+    }
+}
+
+fn rebind_logs(logs: &mut [Log], old: Address, new: Address) {
+    for log in logs.iter_mut().filter(|log| log.address == old) {
+        log.address = new;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SlotWrite, StructLogReader};
+
+    const TRACE: &str = r#"
+    {
+        "jsonrpc": "2.0",
+        "id": 1,
+        "result": {
+            "structLogs": [
+                { "pc": 0, "op": "PUSH1", "gas": 1000, "gasCost": 3, "depth": 1, "stack": [] },
+                { "pc": 2, "op": "CALL", "gas": 997, "gasCost": 700, "depth": 1, "stack": [] },
+                { "pc": 0, "op": "PUSH1", "gas": 200, "gasCost": 3, "depth": 2, "stack": [] },
+                { "pc": 2, "op": "STOP", "gas": 197, "gasCost": 0, "depth": 2, "stack": [] },
+                { "pc": 3, "op": "STOP", "gas": 290, "gasCost": 0, "depth": 1, "stack": [] }
+            ]
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_builder_from_stream() {
+        let mut builder = CallTreeBuilder::new();
+        for step in StructLogReader::new(TRACE.as_bytes()) {
+            builder.push(step.unwrap()).unwrap();
+        }
+        let root = builder.finish().unwrap();
+
+        assert_eq!(root.call_type, CallType::Root);
+        assert_eq!(root.instructions.len(), 3);
+        assert_eq!(root.children.len(), 1);
+        assert_eq!(root.children[0].call_type, CallType::Call);
+        assert_eq!(root.children[0].instructions.len(), 2);
+    }
+
+    #[test]
+    fn test_builder_without_instructions() {
+        let mut builder = CallTreeBuilder::new().store_instructions(false);
+        for step in StructLogReader::new(TRACE.as_bytes()) {
+            builder.push(step.unwrap()).unwrap();
+        }
+        let root = builder.finish().unwrap();
+
+        assert!(root.instructions.is_empty());
+        assert_eq!(root.children.len(), 1);
+        assert_eq!(root.children[0].call_type, CallType::Call);
+        assert!(root.children[0].instructions.is_empty());
+        assert_eq!((root.step_count, root.children[0].step_count), (3, 2));
+    }
+
+    // nested CALLs down to `depth`, where one more CALL fails without entering its callee
+    fn failed_call_at(depth: u64) -> CallFrame {
+        let call = |d: u64| format!(
+            r#"{{ "pc": 0, "op": "CALL", "gas": {}, "depth": {}, "stack": ["0x0", "0x0", "0x0", "0x0", "0x0", "0xcc", "0x64"] }}"#,
+            1_000_000 - d, d
+        );
+        let mut steps: Vec<String> = (1..=depth).map(call).collect();
+        steps.push(format!(r#"{{ "pc": 1, "op": "POP", "gas": 1000, "depth": {}, "stack": ["0x0"] }}"#, depth));
+        let trace = format!(r#"{{ "structLogs": [{}] }}"#, steps.join(","));
+
+        let mut builder = CallTreeBuilder::new().store_instructions(false);
+        for step in StructLogReader::new(std::io::Cursor::new(trace)) {
+            builder.push(step.unwrap()).unwrap();
+        }
+        let mut frame = builder.finish().unwrap();
+        for _ in 0..depth {
+            frame = frame.children.remove(0);
+        }
+        frame
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        // a call from depth 1024 is still allowed, failing there is something else
+        let failed = failed_call_at(1024);
+        assert!(!failed.success);
+        assert_ne!(failed.error, Some(FrameError::CallDepthExceeded));
+
+        let failed = failed_call_at(1025);
+        assert_eq!(failed.error, Some(FrameError::CallDepthExceeded));
+    }
+
+    #[test]
+    fn test_empty_trace() {
+        assert!(CallTreeBuilder::new().finish().is_err());
+    }
+
+    // root B calls C (value 5, selector 0xaabbccdd), C delegatecalls D which returns 0x2a,
+    // then B deploys a contract with CREATE
+    const CALLS: &str = r#"
+    {
+        "structLogs": [
+            { "pc": 0, "op": "CALL", "gas": 10000, "depth": 1,
+              "stack": ["0x20", "0x0", "0x4", "0x0", "0x5", "0xcc", "0x1388"],
+              "memory": ["0xaabbccdd00000000000000000000000000000000000000000000000000000000"] },
+            { "pc": 0, "op": "DELEGATECALL", "gas": 5000, "depth": 2,
+              "stack": ["0x0", "0x0", "0x0", "0x0", "0xdd", "0x3e8"] },
+            { "pc": 0, "op": "PUSH1", "gas": 1000, "depth": 3, "stack": [] },
+            { "pc": 2, "op": "RETURN", "gas": 997, "depth": 3, "stack": ["0x20", "0x0"],
+              "memory": ["0x000000000000000000000000000000000000000000000000000000000000002a"] },
+            { "pc": 1, "op": "STOP", "gas": 4000, "depth": 2, "stack": ["0x1"] },
+            { "pc": 1, "op": "POP", "gas": 9000, "depth": 1, "stack": ["0x1"] },
+            { "pc": 2, "op": "CREATE", "gas": 8000, "depth": 1, "stack": ["0x2", "0x0", "0x0"],
+              "memory": ["0x6000000000000000000000000000000000000000000000000000000000000000"] },
+            { "pc": 0, "op": "STOP", "gas": 3000, "depth": 2, "stack": [] },
+            { "pc": 2, "op": "STOP", "gas": 2000, "depth": 1, "stack": ["0xee"] }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn test_frames_carry_operands() {
+        let addr = |b: u8| Address::with_last_byte(b);
+
+        let mut builder = CallTreeBuilder::new()
+            .with_transaction(addr(0xaa), addr(0xbb), Word::ZERO, vec![0x01]);
+        for step in StructLogReader::new(CALLS.as_bytes()) {
+            builder.push(step.unwrap()).unwrap();
+        }
+        let root = builder.finish().unwrap();
+
+        assert_eq!(root.from, addr(0xaa));
+        assert_eq!(root.to, addr(0xbb));
+        assert_eq!(root.calldata, vec![0x01]);
+        assert_eq!(root.children.len(), 2);
+
+        let call = &root.children[0];
+        assert_eq!(call.call_type, CallType::Call);
+        assert_eq!(call.from, addr(0xbb));
+        assert_eq!(call.to, addr(0xcc));
+        assert_eq!(call.value, Word::from_u64(5));
+        assert_eq!(call.calldata, vec![0xaa, 0xbb, 0xcc, 0xdd]);
+        assert!(call.success);
+
+        // DELEGATECALL runs D's code in C's context with C's caller value
+        let delegate = &call.children[0];
+        assert_eq!(delegate.call_type, CallType::DelegateCall);
+        assert_eq!(delegate.from, addr(0xcc));
+        assert_eq!(delegate.to, addr(0xdd));
+        assert_eq!(delegate.storage_address, addr(0xcc));
+        assert_eq!(delegate.value, Word::from_u64(5));
+        assert_eq!(delegate.return_data.len(), 32);
+        assert_eq!(delegate.return_data[31], 0x2a);
+
+        let create = &root.children[1];
+        assert_eq!(create.call_type, CallType::Create);
+        assert_eq!(create.value, Word::ZERO);
+        assert_eq!(create.calldata, vec![0x60, 0x00]);
+        assert_eq!(create.to, addr(0xee));
+        assert_eq!(create.storage_address, addr(0xee));
+        assert!(create.success);
+    }
+
+    // B reads slot 1 (geth storage map) and writes it, calls C which writes and reverts,
+    // then delegatecalls D which reads slot 1 without a storage map and writes it again
+    const STORAGE: &str = r#"
+    {
+        "structLogs": [
+            { "pc": 0, "op": "SLOAD", "gas": 10000, "depth": 1, "stack": ["0x1"],
+              "storage": { "0000000000000000000000000000000000000000000000000000000000000001": "0000000000000000000000000000000000000000000000000000000000000005" } },
+            { "pc": 1, "op": "SSTORE", "gas": 9000, "depth": 1, "stack": ["0x7", "0x1"] },
+            { "pc": 2, "op": "CALL", "gas": 8000, "depth": 1,
+              "stack": ["0x0", "0x0", "0x0", "0x0", "0x0", "0xcc", "0x1388"] },
+            { "pc": 0, "op": "SSTORE", "gas": 5000, "depth": 2, "stack": ["0x9", "0x2"] },
+            { "pc": 1, "op": "REVERT", "gas": 4000, "depth": 2, "stack": ["0x0", "0x0"] },
+            { "pc": 3, "op": "POP", "gas": 7500, "depth": 1, "stack": ["0x0"] },
+            { "pc": 4, "op": "DELEGATECALL", "gas": 7000, "depth": 1,
+              "stack": ["0x0", "0x0", "0x0", "0x0", "0x0", "0xdd", "0x3e8"] },
+            { "pc": 0, "op": "SLOAD", "gas": 900, "depth": 2, "stack": ["0x1"] },
+            { "pc": 1, "op": "POP", "gas": 800, "depth": 2, "stack": ["0x7"] },
+            { "pc": 2, "op": "SSTORE", "gas": 798, "depth": 2, "stack": ["0x8", "0x1"] },
+            { "pc": 3, "op": "STOP", "gas": 700, "depth": 2, "stack": [] },
+            { "pc": 5, "op": "STOP", "gas": 6000, "depth": 1, "stack": ["0x1"] }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn test_storage_reads_and_writes() {
+        let addr = |b: u8| Address::with_last_byte(b);
+        let word = Word::from_u64;
+
+        let mut builder = CallTreeBuilder::new()
+            .with_transaction(addr(0xaa), addr(0xbb), Word::ZERO, Vec::new());
+        for step in StructLogReader::new(STORAGE.as_bytes()) {
+            builder.push(step.unwrap()).unwrap();
+        }
+        let root = builder.finish().unwrap();
+
+        // the reverted call's write is gone everywhere, its frame included
+        let call = &root.children[0];
+        assert!(!call.success);
+        assert!(call.storage.writes.is_empty());
+        assert!(!root.storage.writes.contains_key(&addr(0xcc)));
+
+        // D's code works on B's storage and sees B's earlier write
+        let delegate = &root.children[1];
+        assert_eq!(delegate.storage.reads[&addr(0xbb)][&word(1)], word(7));
+        assert_eq!(delegate.storage.writes[&addr(0xbb)][&word(1)], SlotWrite { original: Some(word(7)), new: word(8) });
+
+        assert_eq!(root.storage.reads[&addr(0xbb)][&word(1)], word(5));
+        assert_eq!(root.storage.writes.len(), 1);
+        assert_eq!(root.storage.writes[&addr(0xbb)][&word(1)], SlotWrite { original: Some(word(5)), new: word(8) });
+    }
+
+    #[test]
+    fn test_sload_without_storage_map_feeds_later_writes() {
+        let addr = |b: u8| Address::with_last_byte(b);
+        let word = Word::from_u64;
+        let trace = r#"{"structLogs": [
+            { "pc": 0, "op": "SLOAD", "gas": 1000, "depth": 1, "stack": ["0x1"] },
+            { "pc": 1, "op": "PUSH1", "gas": 900, "depth": 1, "stack": ["0x5"] },
+            { "pc": 3, "op": "SSTORE", "gas": 897, "depth": 1, "stack": ["0x9", "0x1"] },
+            { "pc": 4, "op": "STOP", "gas": 800, "depth": 1, "stack": [] }
+        ]}"#;
+
+        let mut builder = CallTreeBuilder::new()
+            .with_transaction(addr(0xaa), addr(0xbb), Word::ZERO, Vec::new());
+        for step in StructLogReader::new(trace.as_bytes()) {
+            builder.push(step.unwrap()).unwrap();
+        }
+        let root = builder.finish().unwrap();
+
+        assert_eq!(root.storage.reads[&addr(0xbb)][&word(1)], word(5));
+        assert_eq!(root.storage.writes[&addr(0xbb)][&word(1)], SlotWrite { original: Some(word(5)), new: word(9) });
+    }
+
+    #[test]
+    fn test_refund_taken_back_is_negative() {
+        // the callee restores a slot the caller cleared, its net refund change is negative
+        let trace = r#"{"structLogs": [
+            { "pc": 0, "op": "CALL", "gas": 9000, "depth": 1, "refund": 4800,
+              "stack": ["0x0", "0x0", "0x0", "0x0", "0x0", "0xcc", "0x1388"] },
+            { "pc": 0, "op": "SSTORE", "gas": 5000, "gasCost": 2900, "depth": 2, "refund": 4800, "stack": ["0x1", "0x1"] },
+            { "pc": 1, "op": "STOP", "gas": 2100, "depth": 2, "refund": 0, "stack": [] },
+            { "pc": 1, "op": "STOP", "gas": 6100, "depth": 1, "refund": 0, "stack": ["0x1"] }
+        ]}"#;
+
+        let mut builder = CallTreeBuilder::new();
+        for step in StructLogReader::new(trace.as_bytes()) {
+            builder.push(step.unwrap()).unwrap();
+        }
+        let root = builder.finish().unwrap();
+
+        assert_eq!(root.children[0].gas_refund, -4800);
+        assert_eq!(root.gas_refund, -4800);
+    }
+
+    // B logs, calls C which logs and reverts, then delegatecalls D which logs in B's name
+    const LOGS: &str = r#"
+    {
+        "structLogs": [
+            { "pc": 0, "op": "LOG1", "gas": 10000, "depth": 1, "stack": ["0x1234", "0x2", "0x0"],
+              "memory": ["0xabcd000000000000000000000000000000000000000000000000000000000000"] },
+            { "pc": 1, "op": "CALL", "gas": 9000, "depth": 1,
+              "stack": ["0x0", "0x0", "0x0", "0x0", "0x0", "0xcc", "0x1388"] },
+            { "pc": 0, "op": "LOG0", "gas": 5000, "depth": 2, "stack": ["0x0", "0x0"] },
+            { "pc": 1, "op": "REVERT", "gas": 4000, "depth": 2, "stack": ["0x0", "0x0"] },
+            { "pc": 2, "op": "POP", "gas": 8000, "depth": 1, "stack": ["0x0"] },
+            { "pc": 3, "op": "DELEGATECALL", "gas": 7000, "depth": 1,
+              "stack": ["0x0", "0x0", "0x0", "0x0", "0xdd", "0x3e8"] },
+            { "pc": 0, "op": "LOG2", "gas": 900, "depth": 2, "stack": ["0x2", "0x1", "0x0", "0x0"] },
+            { "pc": 1, "op": "STOP", "gas": 800, "depth": 2, "stack": [] },
+            { "pc": 4, "op": "STOP", "gas": 6000, "depth": 1, "stack": ["0x1"] }
+        ]
+    }
+    "#;
+
+    #[test]
+    fn test_extracts_logs() {
+        let addr = |b: u8| Address::with_last_byte(b);
+        let topic = |n: u64| Word::from_u64(n).to_b256();
+
+        let mut builder = CallTreeBuilder::new()
+            .with_transaction(addr(0xaa), addr(0xbb), Word::ZERO, Vec::new());
+        for step in StructLogReader::new(LOGS.as_bytes()) {
+            builder.push(step.unwrap()).unwrap();
+        }
+        let root = builder.finish().unwrap();
+
+        assert_eq!(root.logs.len(), 1);
+        assert_eq!(root.logs[0].address, addr(0xbb));
+        assert_eq!(root.logs[0].topics, vec![topic(0x1234)]);
+        assert_eq!(root.logs[0].data, vec![0xab, 0xcd]);
+
+        let logs = root.logs_in_order();
+        assert_eq!(logs.iter().map(|l| l.position).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert!(logs[1].discarded && logs[1].address == addr(0xcc));
+        assert!(!logs[2].discarded);
+        assert_eq!(logs[2].address, addr(0xbb));
+        assert_eq!(logs[2].topics, vec![topic(1), topic(2)]);
     }
-}
\ No newline at end of file
+}
@@ -1,6 +1,9 @@
+use std::collections::BTreeMap;
 use serde::{Serialize, Deserialize};
-use crate::{Word, Opcode, Instruction};
-use alloy_primitives::Address;
+use thiserror::Error;
+use crate::{Word, Instruction};
+use crate::abi::{Decoded, FrameAbi};
+use alloy_primitives::{Address, B256};
 
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -11,28 +14,114 @@ pub enum CallType{
     Create,
     Create2,
     CallCode,
+    // EOF (EIP-7069, EIP-7620)
+    ExtCall,
+    ExtDelegateCall,
+    ExtStaticCall,
+    EofCreate,
     Root,           // top-level trnx
 }
 
+// why a frame did not complete
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Error)]
+pub enum FrameError {
+    #[error("execution reverted")]
+    Reverted,
+    #[error("out of gas")]
+    OutOfGas,
+    #[error("invalid opcode")]
+    InvalidOpcode,
+    #[error("stack underflow")]
+    StackUnderflow,
+    #[error("stack overflow")]
+    StackOverflow,
+    #[error("invalid jump destination")]
+    InvalidJump,
+    #[error("write protection")]       // state change inside a STATICCALL
+    WriteProtection,
+    #[error("max call depth exceeded")]
+    CallDepthExceeded,
+    #[error("insufficient balance for transfer")]
+    InsufficientBalance,
+    #[error("contract address collision")]
+    AddressCollision,
+    #[error("code deposit failed")]    // code too large, starts with 0xEF or cannot pay for storage
+    CodeDepositFailed,
+    #[error("{0}")]
+    Other(String),
+}
+
+impl FrameError {
+    // maps the 'error' string geth-style struct loggers attach to the failing step
+    pub fn from_message(msg: &str) -> Self {
+        let m = msg.to_ascii_lowercase();
+
+        if m.contains("reverted") {
+            FrameError::Reverted
+        } else if m.contains("code storage out of gas") || m.contains("max code size") || m.contains("invalid code") {
+            FrameError::CodeDepositFailed
+        } else if m.contains("out of gas") || m.contains("gas uint64 overflow") {
+            FrameError::OutOfGas
+        } else if m.contains("invalid opcode") || m.contains("not defined") {
+            FrameError::InvalidOpcode
+        } else if m.contains("stack underflow") {
+            FrameError::StackUnderflow
+        } else if m.contains("stack limit") || m.contains("stack overflow") {
+            FrameError::StackOverflow
+        } else if m.contains("jump") {
+            FrameError::InvalidJump
+        } else if m.contains("write protection") || m.contains("static") {
+            FrameError::WriteProtection
+        } else if m.contains("call depth") {
+            FrameError::CallDepthExceeded
+        } else if m.contains("insufficient balance") {
+            FrameError::InsufficientBalance
+        } else if m.contains("collision") {
+            FrameError::AddressCollision
+        } else {
+            FrameError::Other(msg.to_string())
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CallFrame {
     pub call_type: CallType,
     pub to: Address,            // 'to' is usually the address of the code currently executing.
     pub from: Address,
 
+    // account whose storage/balance this frame operates on.
+    // same as 'to' except for DELEGATECALL, EXTDELEGATECALL and CALLCODE which keep the caller's context
+    pub storage_address: Address,
+
     pub value: Word,
 
     pub return_data: Vec<u8>,
     pub calldata: Vec<u8>,
 
     pub gas_limit: u64,
-    pub gas_used: u64,
+    pub gas_used: u64,             // execution gas, refunds are only paid out at the end of the tx
+    pub gas_refund: i64,           // net change of the refund counter while this frame ran, negative when refunds were taken back
 
     //result of this frame
     pub success: bool,
-    pub error: Option<String>,
+    pub error: Option<FrameError>,
+    pub selfdestruct: Option<Address>,     // beneficiary when the frame ended in SELFDESTRUCT
+
+    // slots this frame and its callees read and wrote, see StorageAccess
+    #[serde(default)]
+    pub storage: StorageAccess,
+
+    // LOG0-LOG4 emitted by this frame's own code
+    #[serde(default)]
+    pub logs: Vec<Log>,
+
+    // filled in by AbiRegistry::annotate
+    #[serde(default)]
+    pub abi: Option<FrameAbi>,
 
     pub instructions: Vec<Instruction>,
+    pub step_count: u64,           // steps executed by this frame's own code, kept even without instructions
     pub children: Vec<CallFrame>
 
 
@@ -44,16 +133,106 @@ impl CallFrame {
             call_type,
             from,
             to,
+            storage_address: to,
             value: Word::ZERO,
             return_data: Vec::new(),
             calldata: Vec::new(),
             gas_limit: gas,
             gas_used: 0,
+            gas_refund: 0,
             success: true,
             error: None,
+            selfdestruct: None,
+            storage: StorageAccess::default(),
+            logs: Vec::new(),
+            abi: None,
             instructions: Vec::new(),
+            step_count: 0,
             children: Vec::new(),
         }
     }
+
+    // every log of this frame and its callees in emission order, discarded ones included
+    pub fn logs_in_order(&self) -> Vec<&Log> {
+        fn collect<'a>(frame: &'a CallFrame, out: &mut Vec<&'a Log>) {
+            out.extend(&frame.logs);
+            for child in &frame.children {
+                collect(child, out);
+            }
+        }
+        let mut logs = Vec::new();
+        collect(self, &mut logs);
+        logs.sort_by_key(|log| log.position);
+        logs
+    }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Log {
+    pub address: Address,           // the storage context, i.e. the caller under DELEGATECALL
+    pub topics: Vec<B256>,
+    pub data: Vec<u8>,
+    pub position: u64,              // emission order within the tx, counting discarded logs too
+    pub discarded: bool,            // emitted by a frame that failed or ran under one, not in the receipt
+    #[serde(default)]
+    pub decoded: Option<Decoded>,   // filled in by AbiRegistry::annotate
+}
+
+// one slot written by a frame: the value before its first write and after its last
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotWrite {
+    pub original: Option<Word>,     // None when the trace never showed the slot before the write
+    pub new: Word,
+}
+
+// SLOAD/SSTORE effects of a frame including its callees, keyed by the account that owns
+// the storage (the caller under DELEGATECALL). writes only hold what survived: anything
+// done inside a frame that failed, or under one, is rolled back
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageAccess {
+    pub reads: BTreeMap<Address, BTreeMap<Word, Word>>,     // first value read from each slot
+    pub writes: BTreeMap<Address, BTreeMap<Word, SlotWrite>>,
+}
+
+impl StorageAccess {
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty() && self.writes.is_empty()
+    }
+
+    pub(crate) fn record_read(&mut self, owner: Address, slot: Word, value: Word) {
+        self.reads.entry(owner).or_default().entry(slot).or_insert(value);
+    }
+
+    pub(crate) fn record_write(&mut self, owner: Address, slot: Word, original: Option<Word>, new: Word) {
+        self.writes.entry(owner).or_default()
+            .entry(slot)
+            .and_modify(|w| w.new = new)
+            .or_insert(SlotWrite { original, new });
+    }
+
+    // folds in a callee that ran after everything already recorded here
+    pub(crate) fn merge(&mut self, callee: &StorageAccess, keep_writes: bool) {
+        for (owner, slots) in &callee.reads {
+            for (slot, value) in slots {
+                self.record_read(*owner, *slot, *value);
+            }
+        }
+        if keep_writes {
+            for (owner, slots) in &callee.writes {
+                for (slot, write) in slots {
+                    self.record_write(*owner, *slot, write.original, write.new);
+                }
+            }
+        }
+    }
+
+    // moves a create's placeholder entries to the address it ended up at
+    pub(crate) fn rebind(&mut self, old: Address, new: Address) {
+        if let Some(slots) = self.reads.remove(&old) {
+            self.reads.entry(new).or_default().extend(slots);
+        }
+        if let Some(slots) = self.writes.remove(&old) {
+            self.writes.entry(new).or_default().extend(slots);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// how an artifact is stored on disk, the decompressed bytes are always what the node sent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+    Gzip,
+}
+
+impl Compression {
+    // plain JSON never starts with either magic, it starts with whitespace or '{'
+    pub fn detect(prefix: &[u8]) -> Self {
+        if prefix.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else if prefix.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else {
+            Compression::None
+        }
+    }
+}
+
+// opens an artifact for streaming reads, decompressing on the fly when it is zstd or gzip
+pub fn open_artifact(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let compression = Compression::detect(reader.fill_buf()?);
+
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+        // MultiGzDecoder so concatenated members read as one stream like gunzip does
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_reads_compressed_artifacts() {
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"structLogs":[]}}"#;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(body).unwrap();
+        let files = [
+            ("plain", body.to_vec()),
+            ("zstd", zstd::encode_all(&body[..], 0).unwrap()),
+            ("gzip", gz.finish().unwrap()),
+        ];
+
+        for (name, bytes) in files {
+            let path = dir.join(name);
+            std::fs::write(&path, bytes).unwrap();
+
+            let mut out = Vec::new();
+            open_artifact(&path).unwrap().read_to_end(&mut out).unwrap();
+            assert_eq!(out, body, "{}", name);
+        }
+    }
+}
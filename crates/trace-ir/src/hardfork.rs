@@ -0,0 +1,35 @@
+use serde::{Serialize, Deserialize};
+
+// mainnet upgrades that changed the instruction set or its semantics, in activation order,
+// followed by instruction sets that no mainnet fork has activated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Hardfork {
+    Frontier,
+    Homestead,
+    TangerineWhistle,
+    SpuriousDragon,
+    Byzantium,
+    Constantinople,
+    Petersburg,
+    Istanbul,
+    Berlin,
+    London,
+    Paris,          // the merge, DIFFICULTY becomes PREVRANDAO
+    Shanghai,
+    Cancun,
+    Prague,
+    Osaka,
+    Eof,            // EOF (EIP-7692), dropped from Osaka and not scheduled for any fork
+}
+
+impl Hardfork {
+    // the latest mainnet fork, Eof and anything after it is never reached by default
+    pub const LATEST: Hardfork = Hardfork::Osaka;
+}
+
+impl Default for Hardfork {
+    fn default() -> Self {
+        Hardfork::LATEST
+    }
+}
@@ -1,19 +1,36 @@
 // private modules
 mod word;
 mod opcode;
+mod hardfork;
+mod quirks;
+mod compression;
 
 pub mod call_frame;
 pub mod analysis;
-pub use call_frame::{CallFrame, CallType};
-pub use word::Word;
+pub mod parser;
+pub mod render;
+pub mod state_diff;
+pub mod abi;
+pub use call_frame::{CallFrame, CallType, FrameError, Log, StorageAccess, SlotWrite};
+pub use quirks::Quirks;
+pub use parser::StructLogReader;
+pub use compression::{Compression, open_artifact};
+pub use render::{TreeRenderer, StepFormatter};
+pub use word::{Compact, ParseWordError, Word};
 pub use opcode::{Opcode, OpcodeInfo};
+pub use hardfork::Hardfork;
+pub use abi::{AbiRegistry, Decoded, Param, Revert, FrameAbi};
+pub use state_diff::{StateDiff, AccountDiff, PrestateDiff, PrestateAccount, DiffMismatch};
 
 
-use serde::{Serialize, Deserialize};
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize, Deserializer};
 
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+const MAX_MEMORY_READ: usize = 1 << 25;
+
+// the canonical form every client's structLogs are normalized into, see Quirks
+#[derive(Debug, Clone, Serialize)]
 pub struct Instruction{
     pub pc: u64,
     
@@ -25,19 +42,67 @@ pub struct Instruction{
     #[serde(rename="gasCost")]
     pub gas_cost: Option<u64>,
 
-    #[serde(default)]
     pub stack: Vec<Word>,
 
     pub depth: u64,
 
-    #[serde(default)]
     pub memory: Option<Vec<Word>>,
+
+    // slots of the current contract touched so far, geth only sends it on SLOAD/SSTORE
+    pub storage: Option<BTreeMap<Word, Word>>,
+
+    // refund counter before this step
+    pub refund: Option<u64>,
+
+    // set on the step that halted its frame exceptionally
+    pub error: Option<String>,
+}
+
+// only the canonical encoding, client quirks are handled by StructLogReader
+impl<'de> Deserialize<'de> for Instruction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = quirks::RawStep::deserialize(deserializer)?;
+        Quirks::CANONICAL.decode(raw).map_err(serde::de::Error::custom)
+    }
 }
 
 impl Instruction {
     pub fn info(&self)->OpcodeInfo {
         self.opcode.info()
     }
+
+    // n-th item from the top of the stack, structLogs list the top of the stack last
+    pub fn stack_top(&self, n: usize) -> Option<&Word> {
+        self.stack.iter().rev().nth(n)
+    }
+
+    // memory[offset..offset+size] as seen before this step executes.
+    // reads past the end are zero, same as the EVM after memory expansion
+    pub fn memory_slice(&self, offset: &Word, size: &Word) -> Vec<u8> {
+        let (Some(offset), Some(size)) = (offset.to_usize(), size.to_usize()) else {
+            return Vec::new();
+        };
+        // no real tx can pay for this much memory, the operands are garbage
+        if size > MAX_MEMORY_READ || offset > MAX_MEMORY_READ {
+            return Vec::new();
+        }
+        let Some(memory) = &self.memory else {
+            return Vec::new();
+        };
+
+        let mut out = vec![0u8; size];
+        for (i, byte) in out.iter_mut().enumerate() {
+            let pos = offset + i;
+            match memory.get(pos / 32) {
+                Some(word) => *byte = word.byte(pos % 32),
+                None => break,
+            }
+        }
+        out
+    }
 }
 
 
@@ -46,7 +111,6 @@ mod tests {
     // brings everything from the parent module
     use super::*;
     use alloy_primitives::U256;
-    use serde_json;
 
     #[test]
     fn test_opcode_metadata() {
@@ -54,12 +118,44 @@ mod tests {
         let info = op.info();
 
         assert_eq!(info.name, "ADD");
-        assert_eq!(info.bytes, 0x01);
+        assert_eq!(info.bytes, Some(0x01));
         assert_eq!(info.inputs, 2);
         assert_eq!(info.outputs, 1);
         assert!(!info.is_halt);
     }
 
+    #[test]
+    fn test_opcode_forks() {
+        assert_eq!(Opcode::from_u8(0x5E), Opcode::MCOPY);
+        assert_eq!(Opcode::from_u8(0x4A), Opcode::BLOBBASEFEE);
+        assert_eq!(Opcode::from_u8(0x1E), Opcode::CLZ);
+        assert_eq!(Opcode::from_u8_at(0x1E, Hardfork::Prague), Opcode::INVALID);
+        assert_eq!(Opcode::from_u8_at(0x1E, Hardfork::Osaka), Opcode::CLZ);
+        // EOF bytes are invalid on every mainnet fork, only an explicit Eof decodes them
+        assert_eq!(Opcode::from_u8(0xF8), Opcode::INVALID);
+        assert!(!Opcode::from_u8(0xF8).info().is_call);
+        assert_eq!(Opcode::from_u8(0xE0), Opcode::INVALID);
+        // from a trace they keep their byte
+        assert_eq!(Opcode::from_trace_byte(0xE0), Opcode::Unknown(0xE0));
+        assert_eq!(Opcode::from_u8_at(0xF8, Hardfork::Eof), Opcode::EXTCALL);
+
+        assert_eq!(Opcode::from_u8_at(0x5E, Hardfork::Shanghai), Opcode::INVALID);
+        assert_eq!(Opcode::from_u8_at(0x5E, Hardfork::Cancun), Opcode::MCOPY);
+        assert_eq!(Opcode::from_u8_at(0x5F, Hardfork::London), Opcode::INVALID);
+        assert_eq!(Opcode::from_u8_at(0xF4, Hardfork::Frontier), Opcode::INVALID);
+        assert_eq!(Opcode::from_u8_at(0xF4, Hardfork::Homestead), Opcode::DELEGATECALL);
+        assert_eq!(Opcode::from_u8_at(0xE0, Hardfork::Prague), Opcode::INVALID);
+        // EOF did not ship with Osaka, only the Eof marker has its opcodes
+        assert_eq!(Opcode::from_u8_at(0xE0, Hardfork::Osaka), Opcode::INVALID);
+        assert_eq!(Opcode::from_u8_at(0xE0, Hardfork::LATEST), Opcode::INVALID);
+        assert!((0..=u8::MAX).all(|b| Opcode::from_u8(b) == Opcode::from_u8_at(b, Hardfork::LATEST)));
+        assert_eq!(Opcode::from_u8_at(0xE0, Hardfork::Eof), Opcode::RJUMP);
+        assert_eq!(Opcode::from_u8_at(0x01, Hardfork::Frontier), Opcode::ADD);
+
+        let op: Opcode = serde_json::from_str(r#""EXTDELEGATECALL""#).unwrap();
+        assert_eq!(op.info().fork, Hardfork::Eof);
+    }
+
     #[test]
     fn test_opcode_deserialization(){
         let json = r#""PUSH1""#;
@@ -73,6 +169,62 @@ mod tests {
         assert_eq!(op2, Opcode::INVALID);
     }
 
+    #[test]
+    fn test_unknown_opcodes_round_trip() {
+        assert_eq!(Opcode::from_u8(0xFE), Opcode::INVALID);
+        assert_eq!(Opcode::from_u8(0x0C), Opcode::Unknown(0x0C));
+        assert_eq!(Opcode::from_u8_at(0x0C, Hardfork::Frontier), Opcode::Unknown(0x0C));
+
+        let geth: Opcode = serde_json::from_str(r#""opcode 0xc not defined""#).unwrap();
+        assert_eq!(geth, Opcode::Unknown(0x0C));
+        assert_eq!(geth.to_string(), "UNKNOWN(0x0c)");
+        assert_eq!(serde_json::to_string(&geth).unwrap(), r#""opcode 0xc not defined""#);
+
+        // PUSH0 before Shanghai: the byte was undefined when the tx ran, it must not read as PUSH0
+        let early: Opcode = serde_json::from_str(r#""opcode 0x5f not defined""#).unwrap();
+        assert_eq!(early, Opcode::Unknown(0x5F));
+        assert_eq!(early.info().inputs + early.info().outputs, 0);
+        assert_eq!(serde_json::to_string(&early).unwrap(), r#""opcode 0x5f not defined""#);
+
+        let typo: Opcode = serde_json::from_str(r#""ADDD""#).unwrap();
+        assert_eq!(typo, Opcode::UnknownName("ADDD"));
+        assert_eq!(serde_json::to_string(&typo).unwrap(), r#""ADDD""#);
+        assert!(typo.is_unknown() && !Opcode::INVALID.is_unknown());
+        // an unknown name has no byte, it is not INVALID's 0xFE
+        assert_eq!(typo.info().bytes, None);
+
+        // names no opcode could have are not leaked, but stay recognizably unknown
+        let junk: Opcode = serde_json::from_str(&format!("{:?}", "X".repeat(1000))).unwrap();
+        assert_eq!(junk, Opcode::UnknownNameOverflow);
+        assert!(junk.is_unknown());
+        assert_ne!(junk.info().name, Opcode::Unknown(0x0C).info().name);
+        let json = serde_json::to_string(&junk).unwrap();
+        assert_eq!(serde_json::from_str::<Opcode>(&json).unwrap(), Opcode::UnknownNameOverflow);
+
+        assert!(serde_json::from_str::<Opcode>("256").is_err());
+    }
+
+    #[test]
+    fn test_unknown_instruction_round_trip() {
+        let step = Instruction {
+            pc: 3,
+            opcode: Opcode::Unknown(0x5F),
+            gas: 100,
+            gas_cost: Some(0),
+            stack: vec![Word::from_u64(1)],
+            depth: 2,
+            memory: None,
+            storage: None,
+            refund: None,
+            error: Some("invalid opcode: opcode 0x5f not defined".to_string()),
+        };
+
+        let json = serde_json::to_string(&step).unwrap();
+        let back: Instruction = serde_json::from_str(&json).expect("Should read its own output");
+        assert_eq!(back.opcode, Opcode::Unknown(0x5F));
+        assert_eq!(serde_json::to_string(&back).unwrap(), json);
+    }
+
     #[test]
     fn test_instruction_parsing() {
 
@@ -124,8 +276,47 @@ mod tests {
         // Should be None
         assert_eq!(instruction.gas_cost, None); 
         assert_eq!(instruction.memory, None);   
+        assert_eq!(instruction.storage, None);
+    }
+
+    #[test]
+    fn test_client_encodings() {
+        // unprefixed stack words, the op byte, memory as one blob and an error object
+        let json_data = r#"
+        {
+            "pc": 7,
+            "op": 1,
+            "gas": 100,
+            "gasCost": 3,
+            "depth": 1,
+            "stack": ["00000000000000000000000000000000000000000000000000000000000000ff"],
+            "memory": "0x00000000000000000000000000000000000000000000000000000000000000aa",
+            "error": { "message": "out of gas" }
+        }
+        "#;
+
+        // the canonical encoding does not accept them, the client's quirks have to
+        assert!(serde_json::from_str::<Instruction>(json_data).is_err());
+        let quirks = Quirks { numeric_op: true, memory_blob: true, error_object: true, ..Quirks::CANONICAL };
+        let raw: quirks::RawStep = serde_json::from_str(json_data).unwrap();
+        let instruction = quirks.normalize(raw).expect("Should parse client quirks");
+
+        assert_eq!(instruction.opcode, Opcode::ADD);
+        assert_eq!(instruction.stack, vec![Word::from_u64(0xff)]);
+        assert_eq!(instruction.memory, Some(vec![Word::from_u64(0xaa)]));
+        assert_eq!(instruction.error.as_deref(), Some("out of gas"));
+
+        let renamed: Opcode = serde_json::from_str(r#""KECCAK256""#).unwrap();
+        assert_eq!(renamed, Opcode::SHA3);
     }
 
+    #[test]
+    fn test_null_fields() {
+        let json_data = r#"{ "pc": 0, "op": "STOP", "gas": 0, "depth": 1, "stack": null, "memory": null, "error": null }"#;
+        let instruction: Instruction = serde_json::from_str(json_data).expect("Should parse null fields");
 
-    
-}
\ No newline at end of file
+        assert!(instruction.stack.is_empty());
+        assert_eq!(instruction.memory, None);
+        assert_eq!(instruction.error, None);
+    }
+}
@@ -1,39 +1,79 @@
-use serde::{ Deserialize, Deserializer, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+use serde::{ Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, Visitor};
+
+use crate::Hardfork;
 
 #[derive(Debug, Clone, Copy)]
 pub struct OpcodeInfo {
     pub name : & 'static str,
-    pub bytes : u8,
+    pub bytes : Option<u8>,     // None for a name the table does not know
     pub inputs:  u8,
     pub outputs : u8,
     pub is_call : bool,
     pub is_halt: bool,
+    pub fork: Hardfork,     // first fork the opcode is valid in
 
 }
 
 
 macro_rules! define_opcodes {
     (
-        $($name:ident = $byte:literal {in: $in:literal, out: $out:literal, halt: $halt:expr, call: $call:expr}),*
+        $($name:ident = $byte:literal {in: $in:literal, out: $out:literal, halt: $halt:expr, call: $call:expr, fork: $fork:ident}),*
         $(,)?
     ) => {
 
-        #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize)]
-        #[repr(u8)]    // means the enum uses the actual byte as its discriminator.
+        #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
         pub enum Opcode {
-            $($name = $byte, )*
-            INVALID = 0xFE
+            $($name, )*
+            INVALID,
+            // a byte this table does not define, kept so the trace stays lossless
+            Unknown(u8),
+            // a name this table does not know, e.g. an opcode newer than this crate
+            UnknownName(&'static str),
+            // an unknown name past the interner's caps, only the fact that it was unknown is kept
+            UnknownNameOverflow,
         }
 
         impl Opcode {
 
+            // from_u8_at(hx, Hardfork::LATEST)
             pub fn from_u8(hx: u8)-> Self {
+                Self::from_u8_at(hx, Hardfork::LATEST)
+            }
+
+            // a byte a client reports having executed. unlike from_u8, opcodes LATEST does not
+            // have (e.g. EOF) stay Unknown instead of becoming INVALID, so the byte survives a round trip
+            pub(crate) fn from_trace_byte(hx: u8) -> Self {
+                let op = Self::from_table(hx);
+                if op.is_active(Hardfork::LATEST) { op } else { Opcode::Unknown(hx) }
+            }
 
+            // every byte in the table, whatever fork introduced it
+            fn from_table(hx: u8) -> Self {
                 match hx {
                     $( $byte => Opcode::$name, )*
-                    _ => Opcode::INVALID,
+                    0xFE => Opcode::INVALID,
+                    _ => Opcode::Unknown(hx),
                 }
+            }
+
+            pub fn is_unknown(&self) -> bool {
+                matches!(self, Opcode::Unknown(_) | Opcode::UnknownName(_) | Opcode::UnknownNameOverflow)
+            }
 
+            // like from_u8, but INVALID for table opcodes that `fork` does not have yet,
+            // which includes the EOF opcodes on every fork before Eof.
+            // unknown bytes stay Unknown, the table cannot tell when they were introduced
+            pub fn from_u8_at(hx: u8, fork: Hardfork) -> Self {
+                let op = Self::from_table(hx);
+                if op.is_unknown() || op.is_active(fork) { op } else { Opcode::INVALID }
+            }
+
+            pub fn is_active(&self, fork: Hardfork) -> bool {
+                self.info().fork <= fork
             }
 
             pub fn info(&self) -> OpcodeInfo {
@@ -41,38 +81,114 @@ macro_rules! define_opcodes {
                     $(
                         Opcode::$name => OpcodeInfo{
                             name: stringify!($name),
-                            bytes: $byte,
+                            bytes: Some($byte),
                             inputs: $in,
                             outputs: $out,
                             is_call: $call,
                             is_halt: $halt,
+                            fork: Hardfork::$fork,
                         },
                     )*
                     
                     Opcode::INVALID => OpcodeInfo{
                         name: "INVALID",
-                        bytes: 0xFE,
+                        bytes: Some(0xFE),
                         inputs: 0,
                         outputs: 0,
                         is_call: false,
                         is_halt: true,
-                    }
-                    
+                        fork: Hardfork::Frontier,
+                    },
+
+                    // nothing is known about these, so no stack effect is assumed
+                    Opcode::Unknown(hx) => OpcodeInfo{
+                        name: "UNKNOWN",
+                        bytes: Some(*hx),
+                        inputs: 0,
+                        outputs: 0,
+                        is_call: false,
+                        is_halt: false,
+                        fork: Hardfork::LATEST,
+                    },
+
+                    Opcode::UnknownName(name) => OpcodeInfo{
+                        name: *name,
+                        bytes: None,
+                        inputs: 0,
+                        outputs: 0,
+                        is_call: false,
+                        is_halt: false,
+                        fork: Hardfork::LATEST,
+                    },
+
+                    Opcode::UnknownNameOverflow => OpcodeInfo{
+                        name: "UNKNOWN_NAME_OVERFLOW",
+                        bytes: None,
+                        inputs: 0,
+                        outputs: 0,
+                        is_call: false,
+                        is_halt: false,
+                        fork: Hardfork::LATEST,
+                    },
                 }
             }
         }
         
+        impl fmt::Display for Opcode {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    Opcode::Unknown(hx) => f.pad(&format!("UNKNOWN(0x{:02x})", hx)),
+                    op => f.pad(op.info().name),
+                }
+            }
+        }
+
+        // unknown bytes go out the way geth prints them and unknown names verbatim, so both read back unchanged.
+        // an overflowed name is gone, it goes out as its marker
+        impl Serialize for Opcode {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                match self {
+                    Opcode::Unknown(hx) => serializer.collect_str(&format_args!("opcode {:#x} not defined", hx)),
+                    op => serializer.serialize_str(op.info().name),
+                }
+            }
+        }
+
         // json -> string -> &str -> Opcode matching
         impl <'de> Deserialize <'de> for Opcode {
             fn deserialize<D>(deserializer : D) -> Result<Self, D::Error> 
             where
                 D: Deserializer<'de>,
             {
-                let s = String::deserialize(deserializer)?;
-                match s.as_str() {
+                deserializer.deserialize_any(OpcodeVisitor)
+            }
+        }
+
+        struct OpcodeVisitor;
+
+        impl<'de> Visitor<'de> for OpcodeVisitor {
+            type Value = Opcode;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an opcode name")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                match v {
                     $( stringify!($name) => Ok(Opcode::$name), )*
-                    _ => Ok(Opcode::INVALID),
-                    
+                    // names used by newer clients for the same bytes
+                    "KECCAK256" => Ok(Opcode::SHA3),
+                    "PREVRANDAO" => Ok(Opcode::DIFFICULTY),
+                    "INVALID" => Ok(Opcode::INVALID),
+                    "UNKNOWN_NAME_OVERFLOW" => Ok(Opcode::UnknownNameOverflow),
+                    // geth prints bytes it has no name for as "opcode 0x.. not defined"
+                    _ => match v.strip_prefix("opcode 0x").and_then(|rest| rest.strip_suffix(" not defined")) {
+                        // undefined for the fork the tx ran on, even if a later fork defines it (e.g. PUSH0)
+                        Some(hex) => u8::from_str_radix(hex, 16)
+                            .map(Opcode::Unknown)
+                            .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self)),
+                        None => Ok(intern(v).map_or(Opcode::UnknownNameOverflow, Opcode::UnknownName)),
+                    },
                 }
             }
         }
@@ -80,173 +196,225 @@ macro_rules! define_opcodes {
 
 }
 
+// at most this many unknown names are leaked per process, a broken or hostile trace
+// could otherwise grow the interner without bound
+const MAX_UNKNOWN_NAMES: usize = 256;
+const MAX_UNKNOWN_NAME_LEN: usize = 32;
+
+// unknown names are few per process, leaking them keeps Opcode Copy.
+// None past the caps
+fn intern(name: &str) -> Option<&'static str> {
+    if name.len() > MAX_UNKNOWN_NAME_LEN {
+        return None;
+    }
+    static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let mut names = NAMES.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(known) = names.get(name) {
+        return Some(known);
+    }
+    if names.len() >= MAX_UNKNOWN_NAMES {
+        return None;
+    }
+    let leaked: &'static str = Box::leak(name.to_owned().into_boxed_str());
+    names.insert(leaked);
+    Some(leaked)
+}
+
 define_opcodes! {
     // 0x00 - Stop & Arithmetic
-    STOP       = 0x00 { in: 0, out: 0, halt: true,  call: false },
-    ADD        = 0x01 { in: 2, out: 1, halt: false, call: false },
-    MUL        = 0x02 { in: 2, out: 1, halt: false, call: false },
-    SUB        = 0x03 { in: 2, out: 1, halt: false, call: false },
-    DIV        = 0x04 { in: 2, out: 1, halt: false, call: false },
-    SDIV       = 0x05 { in: 2, out: 1, halt: false, call: false },
-    MOD        = 0x06 { in: 2, out: 1, halt: false, call: false },
-    SMOD       = 0x07 { in: 2, out: 1, halt: false, call: false },
-    ADDMOD     = 0x08 { in: 3, out: 1, halt: false, call: false },
-    MULMOD     = 0x09 { in: 3, out: 1, halt: false, call: false },
-    EXP        = 0x0A { in: 2, out: 1, halt: false, call: false },
-    SIGNEXTEND = 0x0B { in: 2, out: 1, halt: false, call: false },
+    STOP       = 0x00 { in: 0, out: 0, halt: true,  call: false, fork: Frontier },
+    ADD        = 0x01 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    MUL        = 0x02 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    SUB        = 0x03 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    DIV        = 0x04 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    SDIV       = 0x05 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    MOD        = 0x06 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    SMOD       = 0x07 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    ADDMOD     = 0x08 { in: 3, out: 1, halt: false, call: false, fork: Frontier },
+    MULMOD     = 0x09 { in: 3, out: 1, halt: false, call: false, fork: Frontier },
+    EXP        = 0x0A { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    SIGNEXTEND = 0x0B { in: 2, out: 1, halt: false, call: false, fork: Frontier },
 
     // 0x10 - Comparison & Bitwise
-    LT     = 0x10 { in: 2, out: 1, halt: false, call: false },
-    GT     = 0x11 { in: 2, out: 1, halt: false, call: false },
-    SLT    = 0x12 { in: 2, out: 1, halt: false, call: false },
-    SGT    = 0x13 { in: 2, out: 1, halt: false, call: false },
-    EQ     = 0x14 { in: 2, out: 1, halt: false, call: false },
-    ISZERO = 0x15 { in: 1, out: 1, halt: false, call: false },
-    AND    = 0x16 { in: 2, out: 1, halt: false, call: false },
-    OR     = 0x17 { in: 2, out: 1, halt: false, call: false },
-    XOR    = 0x18 { in: 2, out: 1, halt: false, call: false },
-    NOT    = 0x19 { in: 1, out: 1, halt: false, call: false },
-    BYTE   = 0x1A { in: 2, out: 1, halt: false, call: false },
-    SHL    = 0x1B { in: 2, out: 1, halt: false, call: false },
-    SHR    = 0x1C { in: 2, out: 1, halt: false, call: false },
-    SAR    = 0x1D { in: 2, out: 1, halt: false, call: false },
+    LT     = 0x10 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    GT     = 0x11 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    SLT    = 0x12 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    SGT    = 0x13 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    EQ     = 0x14 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    ISZERO = 0x15 { in: 1, out: 1, halt: false, call: false, fork: Frontier },
+    AND    = 0x16 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    OR     = 0x17 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    XOR    = 0x18 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    NOT    = 0x19 { in: 1, out: 1, halt: false, call: false, fork: Frontier },
+    BYTE   = 0x1A { in: 2, out: 1, halt: false, call: false, fork: Frontier },
+    SHL    = 0x1B { in: 2, out: 1, halt: false, call: false, fork: Constantinople },
+    SHR    = 0x1C { in: 2, out: 1, halt: false, call: false, fork: Constantinople },
+    SAR    = 0x1D { in: 2, out: 1, halt: false, call: false, fork: Constantinople },
+    CLZ    = 0x1E { in: 1, out: 1, halt: false, call: false, fork: Osaka }, // EIP-7939
 
     // 0x20 - SHA3
-    SHA3 = 0x20 { in: 2, out: 1, halt: false, call: false },
+    SHA3 = 0x20 { in: 2, out: 1, halt: false, call: false, fork: Frontier },
 
     // 0x30 - Environmental
-    ADDRESS      = 0x30 { in: 0, out: 1, halt: false, call: false },
-    BALANCE      = 0x31 { in: 1, out: 1, halt: false, call: false },
-    ORIGIN       = 0x32 { in: 0, out: 1, halt: false, call: false },
-    CALLER       = 0x33 { in: 0, out: 1, halt: false, call: false },
-    CALLVALUE    = 0x34 { in: 0, out: 1, halt: false, call: false },
-    CALLDATALOAD = 0x35 { in: 1, out: 1, halt: false, call: false },
-    CALLDATASIZE = 0x36 { in: 0, out: 1, halt: false, call: false },
-    CALLDATACOPY = 0x37 { in: 3, out: 0, halt: false, call: false },
-    CODESIZE     = 0x38 { in: 0, out: 1, halt: false, call: false },
-    CODECOPY     = 0x39 { in: 3, out: 0, halt: false, call: false },
-    GASPRICE     = 0x3A { in: 0, out: 1, halt: false, call: false },
-    EXTCODESIZE  = 0x3B { in: 1, out: 1, halt: false, call: false },
-    EXTCODECOPY  = 0x3C { in: 4, out: 0, halt: false, call: false },
-    RETURNDATASIZE = 0x3D { in: 0, out: 1, halt: false, call: false },
-    RETURNDATACOPY = 0x3E { in: 3, out: 0, halt: false, call: false },
-    EXTCODEHASH  = 0x3F { in: 1, out: 1, halt: false, call: false },
+    ADDRESS      = 0x30 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    BALANCE      = 0x31 { in: 1, out: 1, halt: false, call: false, fork: Frontier },
+    ORIGIN       = 0x32 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    CALLER       = 0x33 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    CALLVALUE    = 0x34 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    CALLDATALOAD = 0x35 { in: 1, out: 1, halt: false, call: false, fork: Frontier },
+    CALLDATASIZE = 0x36 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    CALLDATACOPY = 0x37 { in: 3, out: 0, halt: false, call: false, fork: Frontier },
+    CODESIZE     = 0x38 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    CODECOPY     = 0x39 { in: 3, out: 0, halt: false, call: false, fork: Frontier },
+    GASPRICE     = 0x3A { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    EXTCODESIZE  = 0x3B { in: 1, out: 1, halt: false, call: false, fork: Frontier },
+    EXTCODECOPY  = 0x3C { in: 4, out: 0, halt: false, call: false, fork: Frontier },
+    RETURNDATASIZE = 0x3D { in: 0, out: 1, halt: false, call: false, fork: Byzantium },
+    RETURNDATACOPY = 0x3E { in: 3, out: 0, halt: false, call: false, fork: Byzantium },
+    EXTCODEHASH  = 0x3F { in: 1, out: 1, halt: false, call: false, fork: Constantinople },
 
     // 0x40 - Block
-    BLOCKHASH   = 0x40 { in: 1, out: 1, halt: false, call: false },
-    COINBASE    = 0x41 { in: 0, out: 1, halt: false, call: false },
-    TIMESTAMP   = 0x42 { in: 0, out: 1, halt: false, call: false },
-    NUMBER      = 0x43 { in: 0, out: 1, halt: false, call: false },
-    DIFFICULTY  = 0x44 { in: 0, out: 1, halt: false, call: false }, // PREVRANDAO
-    GASLIMIT    = 0x45 { in: 0, out: 1, halt: false, call: false },
-    CHAINID     = 0x46 { in: 0, out: 1, halt: false, call: false },
-    SELFBALANCE = 0x47 { in: 0, out: 1, halt: false, call: false },
-    BASEFEE     = 0x48 { in: 0, out: 1, halt: false, call: false },
-    BLOBHASH    = 0x49 { in: 1, out: 1, halt: false, call: false },
+    BLOCKHASH   = 0x40 { in: 1, out: 1, halt: false, call: false, fork: Frontier },
+    COINBASE    = 0x41 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    TIMESTAMP   = 0x42 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    NUMBER      = 0x43 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    DIFFICULTY  = 0x44 { in: 0, out: 1, halt: false, call: false, fork: Frontier }, // PREVRANDAO
+    GASLIMIT    = 0x45 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    CHAINID     = 0x46 { in: 0, out: 1, halt: false, call: false, fork: Istanbul },
+    SELFBALANCE = 0x47 { in: 0, out: 1, halt: false, call: false, fork: Istanbul },
+    BASEFEE     = 0x48 { in: 0, out: 1, halt: false, call: false, fork: London },
+    BLOBHASH    = 0x49 { in: 1, out: 1, halt: false, call: false, fork: Cancun },
+    BLOBBASEFEE = 0x4A { in: 0, out: 1, halt: false, call: false, fork: Cancun },
 
     // 0x50 - Stack & Memory
-    POP      = 0x50 { in: 1, out: 0, halt: false, call: false },
-    MLOAD    = 0x51 { in: 1, out: 1, halt: false, call: false },
-    MSTORE   = 0x52 { in: 2, out: 0, halt: false, call: false },
-    MSTORE8  = 0x53 { in: 2, out: 0, halt: false, call: false },
-    SLOAD    = 0x54 { in: 1, out: 1, halt: false, call: false },
-    SSTORE   = 0x55 { in: 2, out: 0, halt: false, call: false },
-    JUMP     = 0x56 { in: 1, out: 0, halt: false, call: false },
-    JUMPI    = 0x57 { in: 2, out: 0, halt: false, call: false },
-    PC       = 0x58 { in: 0, out: 1, halt: false, call: false },
-    MSIZE    = 0x59 { in: 0, out: 1, halt: false, call: false },
-    GAS      = 0x5A { in: 0, out: 1, halt: false, call: false },
-    JUMPDEST = 0x5B { in: 0, out: 0, halt: false, call: false },
-    TLOAD    = 0x5C { in: 1, out: 1, halt: false, call: false }, // EIP-1153
-    TSTORE   = 0x5D { in: 2, out: 0, halt: false, call: false }, // EIP-1153
+    POP      = 0x50 { in: 1, out: 0, halt: false, call: false, fork: Frontier },
+    MLOAD    = 0x51 { in: 1, out: 1, halt: false, call: false, fork: Frontier },
+    MSTORE   = 0x52 { in: 2, out: 0, halt: false, call: false, fork: Frontier },
+    MSTORE8  = 0x53 { in: 2, out: 0, halt: false, call: false, fork: Frontier },
+    SLOAD    = 0x54 { in: 1, out: 1, halt: false, call: false, fork: Frontier },
+    SSTORE   = 0x55 { in: 2, out: 0, halt: false, call: false, fork: Frontier },
+    JUMP     = 0x56 { in: 1, out: 0, halt: false, call: false, fork: Frontier },
+    JUMPI    = 0x57 { in: 2, out: 0, halt: false, call: false, fork: Frontier },
+    PC       = 0x58 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    MSIZE    = 0x59 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    GAS      = 0x5A { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    JUMPDEST = 0x5B { in: 0, out: 0, halt: false, call: false, fork: Frontier },
+    TLOAD    = 0x5C { in: 1, out: 1, halt: false, call: false, fork: Cancun }, // EIP-1153
+    TSTORE   = 0x5D { in: 2, out: 0, halt: false, call: false, fork: Cancun }, // EIP-1153
+    MCOPY    = 0x5E { in: 3, out: 0, halt: false, call: false, fork: Cancun }, // EIP-5656
 
     // 0x60 - 0x7F: PUSH Operations
-    PUSH0  = 0x5F { in: 0, out: 1, halt: false, call: false },
-    PUSH1  = 0x60 { in: 0, out: 1, halt: false, call: false },
-    PUSH2  = 0x61 { in: 0, out: 1, halt: false, call: false },
-    PUSH3  = 0x62 { in: 0, out: 1, halt: false, call: false },
-    PUSH4  = 0x63 { in: 0, out: 1, halt: false, call: false },
-    PUSH5  = 0x64 { in: 0, out: 1, halt: false, call: false },
-    PUSH6  = 0x65 { in: 0, out: 1, halt: false, call: false },
-    PUSH7  = 0x66 { in: 0, out: 1, halt: false, call: false },
-    PUSH8  = 0x67 { in: 0, out: 1, halt: false, call: false },
-    PUSH9  = 0x68 { in: 0, out: 1, halt: false, call: false },
-    PUSH10 = 0x69 { in: 0, out: 1, halt: false, call: false },
-    PUSH11 = 0x6A { in: 0, out: 1, halt: false, call: false },
-    PUSH12 = 0x6B { in: 0, out: 1, halt: false, call: false },
-    PUSH13 = 0x6C { in: 0, out: 1, halt: false, call: false },
-    PUSH14 = 0x6D { in: 0, out: 1, halt: false, call: false },
-    PUSH15 = 0x6E { in: 0, out: 1, halt: false, call: false },
-    PUSH16 = 0x6F { in: 0, out: 1, halt: false, call: false },
-    PUSH17 = 0x70 { in: 0, out: 1, halt: false, call: false },
-    PUSH18 = 0x71 { in: 0, out: 1, halt: false, call: false },
-    PUSH19 = 0x72 { in: 0, out: 1, halt: false, call: false },
-    PUSH20 = 0x73 { in: 0, out: 1, halt: false, call: false },
-    PUSH21 = 0x74 { in: 0, out: 1, halt: false, call: false },
-    PUSH22 = 0x75 { in: 0, out: 1, halt: false, call: false },
-    PUSH23 = 0x76 { in: 0, out: 1, halt: false, call: false },
-    PUSH24 = 0x77 { in: 0, out: 1, halt: false, call: false },
-    PUSH25 = 0x78 { in: 0, out: 1, halt: false, call: false },
-    PUSH26 = 0x79 { in: 0, out: 1, halt: false, call: false },
-    PUSH27 = 0x7A { in: 0, out: 1, halt: false, call: false },
-    PUSH28 = 0x7B { in: 0, out: 1, halt: false, call: false },
-    PUSH29 = 0x7C { in: 0, out: 1, halt: false, call: false },
-    PUSH30 = 0x7D { in: 0, out: 1, halt: false, call: false },
-    PUSH31 = 0x7E { in: 0, out: 1, halt: false, call: false },
-    PUSH32 = 0x7F { in: 0, out: 1, halt: false, call: false },
+    PUSH0  = 0x5F { in: 0, out: 1, halt: false, call: false, fork: Shanghai },
+    PUSH1  = 0x60 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH2  = 0x61 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH3  = 0x62 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH4  = 0x63 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH5  = 0x64 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH6  = 0x65 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH7  = 0x66 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH8  = 0x67 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH9  = 0x68 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH10 = 0x69 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH11 = 0x6A { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH12 = 0x6B { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH13 = 0x6C { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH14 = 0x6D { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH15 = 0x6E { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH16 = 0x6F { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH17 = 0x70 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH18 = 0x71 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH19 = 0x72 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH20 = 0x73 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH21 = 0x74 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH22 = 0x75 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH23 = 0x76 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH24 = 0x77 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH25 = 0x78 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH26 = 0x79 { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH27 = 0x7A { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH28 = 0x7B { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH29 = 0x7C { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH30 = 0x7D { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH31 = 0x7E { in: 0, out: 1, halt: false, call: false, fork: Frontier },
+    PUSH32 = 0x7F { in: 0, out: 1, halt: false, call: false, fork: Frontier },
 
     // 0x80 - 0x9F: DUP & SWAP
-    DUP1   = 0x80 { in: 1, out: 2, halt: false, call: false },
-    DUP2   = 0x81 { in: 2, out: 3, halt: false, call: false },
-    DUP3   = 0x82 { in: 3, out: 4, halt: false, call: false },
-    DUP4   = 0x83 { in: 4, out: 5, halt: false, call: false },
-    DUP5   = 0x84 { in: 5, out: 6, halt: false, call: false },
-    DUP6   = 0x85 { in: 6, out: 7, halt: false, call: false },
-    DUP7   = 0x86 { in: 7, out: 8, halt: false, call: false },
-    DUP8   = 0x87 { in: 8, out: 9, halt: false, call: false },
-    DUP9   = 0x88 { in: 9, out: 10, halt: false, call: false },
-    DUP10  = 0x89 { in: 10, out: 11, halt: false, call: false },
-    DUP11  = 0x8A { in: 11, out: 12, halt: false, call: false },
-    DUP12  = 0x8B { in: 12, out: 13, halt: false, call: false },
-    DUP13  = 0x8C { in: 13, out: 14, halt: false, call: false },
-    DUP14  = 0x8D { in: 14, out: 15, halt: false, call: false },
-    DUP15  = 0x8E { in: 15, out: 16, halt: false, call: false },
-    DUP16  = 0x8F { in: 16, out: 17, halt: false, call: false },
-
-    SWAP1  = 0x90 { in: 2, out: 2, halt: false, call: false },
-    SWAP2  = 0x91 { in: 3, out: 3, halt: false, call: false },
-    SWAP3  = 0x92 { in: 4, out: 4, halt: false, call: false },
-    SWAP4  = 0x93 { in: 5, out: 5, halt: false, call: false },
-    SWAP5  = 0x94 { in: 6, out: 6, halt: false, call: false },
-    SWAP6  = 0x95 { in: 7, out: 7, halt: false, call: false },
-    SWAP7  = 0x96 { in: 8, out: 8, halt: false, call: false },
-    SWAP8  = 0x97 { in: 9, out: 9, halt: false, call: false },
-    SWAP9  = 0x98 { in: 10, out: 10, halt: false, call: false },
-    SWAP10 = 0x99 { in: 11, out: 11, halt: false, call: false },
-    SWAP11 = 0x9A { in: 12, out: 12, halt: false, call: false },
-    SWAP12 = 0x9B { in: 13, out: 13, halt: false, call: false },
-    SWAP13 = 0x9C { in: 14, out: 14, halt: false, call: false },
-    SWAP14 = 0x9D { in: 15, out: 15, halt: false, call: false },
-    SWAP15 = 0x9E { in: 16, out: 16, halt: false, call: false },
-    SWAP16 = 0x9F { in: 17, out: 17, halt: false, call: false },
+    DUP1   = 0x80 { in: 1, out: 2, halt: false, call: false, fork: Frontier },
+    DUP2   = 0x81 { in: 2, out: 3, halt: false, call: false, fork: Frontier },
+    DUP3   = 0x82 { in: 3, out: 4, halt: false, call: false, fork: Frontier },
+    DUP4   = 0x83 { in: 4, out: 5, halt: false, call: false, fork: Frontier },
+    DUP5   = 0x84 { in: 5, out: 6, halt: false, call: false, fork: Frontier },
+    DUP6   = 0x85 { in: 6, out: 7, halt: false, call: false, fork: Frontier },
+    DUP7   = 0x86 { in: 7, out: 8, halt: false, call: false, fork: Frontier },
+    DUP8   = 0x87 { in: 8, out: 9, halt: false, call: false, fork: Frontier },
+    DUP9   = 0x88 { in: 9, out: 10, halt: false, call: false, fork: Frontier },
+    DUP10  = 0x89 { in: 10, out: 11, halt: false, call: false, fork: Frontier },
+    DUP11  = 0x8A { in: 11, out: 12, halt: false, call: false, fork: Frontier },
+    DUP12  = 0x8B { in: 12, out: 13, halt: false, call: false, fork: Frontier },
+    DUP13  = 0x8C { in: 13, out: 14, halt: false, call: false, fork: Frontier },
+    DUP14  = 0x8D { in: 14, out: 15, halt: false, call: false, fork: Frontier },
+    DUP15  = 0x8E { in: 15, out: 16, halt: false, call: false, fork: Frontier },
+    DUP16  = 0x8F { in: 16, out: 17, halt: false, call: false, fork: Frontier },
+
+    SWAP1  = 0x90 { in: 2, out: 2, halt: false, call: false, fork: Frontier },
+    SWAP2  = 0x91 { in: 3, out: 3, halt: false, call: false, fork: Frontier },
+    SWAP3  = 0x92 { in: 4, out: 4, halt: false, call: false, fork: Frontier },
+    SWAP4  = 0x93 { in: 5, out: 5, halt: false, call: false, fork: Frontier },
+    SWAP5  = 0x94 { in: 6, out: 6, halt: false, call: false, fork: Frontier },
+    SWAP6  = 0x95 { in: 7, out: 7, halt: false, call: false, fork: Frontier },
+    SWAP7  = 0x96 { in: 8, out: 8, halt: false, call: false, fork: Frontier },
+    SWAP8  = 0x97 { in: 9, out: 9, halt: false, call: false, fork: Frontier },
+    SWAP9  = 0x98 { in: 10, out: 10, halt: false, call: false, fork: Frontier },
+    SWAP10 = 0x99 { in: 11, out: 11, halt: false, call: false, fork: Frontier },
+    SWAP11 = 0x9A { in: 12, out: 12, halt: false, call: false, fork: Frontier },
+    SWAP12 = 0x9B { in: 13, out: 13, halt: false, call: false, fork: Frontier },
+    SWAP13 = 0x9C { in: 14, out: 14, halt: false, call: false, fork: Frontier },
+    SWAP14 = 0x9D { in: 15, out: 15, halt: false, call: false, fork: Frontier },
+    SWAP15 = 0x9E { in: 16, out: 16, halt: false, call: false, fork: Frontier },
+    SWAP16 = 0x9F { in: 17, out: 17, halt: false, call: false, fork: Frontier },
 
     // 0xA0 - Logging
-    LOG0   = 0xA0 { in: 2, out: 0, halt: false, call: false },
-    LOG1   = 0xA1 { in: 3, out: 0, halt: false, call: false },
-    LOG2   = 0xA2 { in: 4, out: 0, halt: false, call: false },
-    LOG3   = 0xA3 { in: 5, out: 0, halt: false, call: false },
-    LOG4   = 0xA4 { in: 6, out: 0, halt: false, call: false },
+    LOG0   = 0xA0 { in: 2, out: 0, halt: false, call: false, fork: Frontier },
+    LOG1   = 0xA1 { in: 3, out: 0, halt: false, call: false, fork: Frontier },
+    LOG2   = 0xA2 { in: 4, out: 0, halt: false, call: false, fork: Frontier },
+    LOG3   = 0xA3 { in: 5, out: 0, halt: false, call: false, fork: Frontier },
+    LOG4   = 0xA4 { in: 6, out: 0, halt: false, call: false, fork: Frontier },
+
+    // 0xD0 - EOF data section (EIP-7480)
+    DATALOAD  = 0xD0 { in: 1, out: 1, halt: false, call: false, fork: Eof },
+    DATALOADN = 0xD1 { in: 0, out: 1, halt: false, call: false, fork: Eof },
+    DATASIZE  = 0xD2 { in: 0, out: 1, halt: false, call: false, fork: Eof },
+    DATACOPY  = 0xD3 { in: 3, out: 0, halt: false, call: false, fork: Eof },
+
+    // 0xE0 - EOF control flow and stack (EIP-4200, 4750, 6206, 663)
+    RJUMP    = 0xE0 { in: 0, out: 0, halt: false, call: false, fork: Eof },
+    RJUMPI   = 0xE1 { in: 1, out: 0, halt: false, call: false, fork: Eof },
+    RJUMPV   = 0xE2 { in: 1, out: 0, halt: false, call: false, fork: Eof },
+    CALLF    = 0xE3 { in: 0, out: 0, halt: false, call: false, fork: Eof },
+    RETF     = 0xE4 { in: 0, out: 0, halt: false, call: false, fork: Eof },
+    JUMPF    = 0xE5 { in: 0, out: 0, halt: false, call: false, fork: Eof },
+    DUPN     = 0xE6 { in: 0, out: 1, halt: false, call: false, fork: Eof }, // depth is an immediate
+    SWAPN    = 0xE7 { in: 0, out: 0, halt: false, call: false, fork: Eof },
+    EXCHANGE = 0xE8 { in: 0, out: 0, halt: false, call: false, fork: Eof },
+
+    // 0xEC - EOF contract creation (EIP-7620)
+    EOFCREATE      = 0xEC { in: 4, out: 1, halt: false, call: true,  fork: Eof },
+    RETURNCONTRACT = 0xEE { in: 2, out: 0, halt: true,  call: false, fork: Eof },
 
     // 0xF0 - System
-    CREATE       = 0xF0 { in: 3, out: 1, halt: false, call: true  },
-    CALL         = 0xF1 { in: 7, out: 1, halt: false, call: true  },
-    CALLCODE     = 0xF2 { in: 7, out: 1, halt: false, call: true  },
-    RETURN       = 0xF3 { in: 2, out: 0, halt: true,  call: false },
-    DELEGATECALL = 0xF4 { in: 6, out: 1, halt: false, call: true  },
-    CREATE2      = 0xF5 { in: 4, out: 1, halt: false, call: true  },
-    STATICCALL   = 0xFA { in: 6, out: 1, halt: false, call: true  },
-    REVERT       = 0xFD { in: 2, out: 0, halt: true,  call: false },
-    SELFDESTRUCT = 0xFF { in: 1, out: 0, halt: true,  call: false },
+    CREATE          = 0xF0 { in: 3, out: 1, halt: false, call: true,  fork: Frontier },
+    CALL            = 0xF1 { in: 7, out: 1, halt: false, call: true,  fork: Frontier },
+    CALLCODE        = 0xF2 { in: 7, out: 1, halt: false, call: true,  fork: Frontier },
+    RETURN          = 0xF3 { in: 2, out: 0, halt: true,  call: false, fork: Frontier },
+    DELEGATECALL    = 0xF4 { in: 6, out: 1, halt: false, call: true,  fork: Homestead },
+    CREATE2         = 0xF5 { in: 4, out: 1, halt: false, call: true,  fork: Constantinople },
+    RETURNDATALOAD  = 0xF7 { in: 1, out: 1, halt: false, call: false, fork: Eof }, // EIP-7069
+    EXTCALL         = 0xF8 { in: 4, out: 1, halt: false, call: true,  fork: Eof },
+    EXTDELEGATECALL = 0xF9 { in: 3, out: 1, halt: false, call: true,  fork: Eof },
+    STATICCALL      = 0xFA { in: 6, out: 1, halt: false, call: true,  fork: Byzantium },
+    EXTSTATICCALL   = 0xFB { in: 3, out: 1, halt: false, call: true,  fork: Eof },
+    REVERT          = 0xFD { in: 2, out: 0, halt: true,  call: false, fork: Byzantium },
+    SELFDESTRUCT    = 0xFF { in: 1, out: 0, halt: true,  call: false, fork: Frontier },
 }
 
 
@@ -0,0 +1,395 @@
+use std::fmt;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use anyhow::{Result, Context, anyhow};
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use crate::{Instruction, Opcode, Quirks};
+use crate::quirks::RawStep;
+use crate::compression::open_artifact;
+
+// steps decoded ahead of the consumer, the only thing the reader keeps in memory
+const READ_AHEAD: usize = 64;
+
+// Streams `Instruction`s out of a debug_traceTransaction response one step at a time.
+// The envelope is walked by a serde visitor (same key matching as RpcEnvelopeVisitor)
+// on a reader thread: everything we do not care about goes through IgnoredAny and
+// every structLogs element is normalized with the client's Quirks and handed over
+// a bounded channel, so memory stays bounded by READ_AHEAD steps.
+pub struct StructLogReader {
+    source: Option<Box<dyn Read + Send>>,
+    received: Option<Receiver<Result<Instruction>>>,
+    walker: Option<JoinHandle<()>>,
+    quirks: Quirks,
+    done: bool,
+    steps: u64,
+    unknown_opcodes: u64,
+    dropped_names: u64,
+    strict: bool,
+}
+
+impl StructLogReader {
+    // zstd and gzip traces are decompressed transparently
+    pub fn open(path: &Path) -> Result<Self> {
+        let reader = open_artifact(path)
+            .with_context(|| format!("could not open trace file {:?}", path))?;
+        Ok(Self::new(reader))
+    }
+
+    // nothing is read until the first step is asked for
+    pub fn new<R: Read + Send + 'static>(reader: R) -> Self {
+        Self {
+            source: Some(Box::new(reader)),
+            received: None,
+            walker: None,
+            quirks: Quirks::CANONICAL,
+            done: false,
+            steps: 0,
+            unknown_opcodes: 0,
+            dropped_names: 0,
+            strict: false,
+        }
+    }
+
+    // how the client that produced the trace deviates from the canonical encoding
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    // fail on the first opcode the table does not know instead of passing it through
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    // number of instructions yielded so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // steps so far whose opcode was not in the table, a node emitting something new
+    pub fn unknown_opcodes(&self) -> u64 {
+        self.unknown_opcodes
+    }
+
+    // of those, steps whose name was past the interner's caps and came out as UnknownNameOverflow
+    pub fn dropped_opcode_names(&self) -> u64 {
+        self.dropped_names
+    }
+}
+
+impl Iterator for StructLogReader {
+    type Item = Result<Instruction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(source) = self.source.take() {
+            let (sender, received) = mpsc::sync_channel(READ_AHEAD);
+            let quirks = self.quirks;
+            self.walker = Some(thread::spawn(move || walk_envelope(source, quirks, sender)));
+            self.received = Some(received);
+        }
+
+        // the reader thread hangs up once the whole envelope has been read,
+        // or when it panicked, which must not pass for the end of the trace
+        let Some(Ok(step)) = self.received.as_ref().map(Receiver::recv) else {
+            self.done = true;
+            return match self.walker.take().map(JoinHandle::join) {
+                Some(Err(_)) => Some(Err(anyhow!("trace reader thread panicked after step {}", self.steps))),
+                _ => None,
+            };
+        };
+        match step {
+            Ok(instr) if instr.opcode.is_unknown() && self.strict => {
+                self.done = true;
+                Some(Err(anyhow!("unknown opcode {} at step {}", instr.opcode, self.steps)))
+            }
+            Ok(instr) => {
+                if instr.opcode.is_unknown() {
+                    self.unknown_opcodes += 1;
+                }
+                if instr.opcode == Opcode::UnknownNameOverflow {
+                    self.dropped_names += 1;
+                }
+                self.steps += 1;
+                Some(Ok(instr))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// the reader thread: every step and at most one error go out through `sender`
+fn walk_envelope<R: Read>(reader: R, quirks: Quirks, sender: SyncSender<Result<Instruction>>) {
+    let mut walk = Walk {
+        sender: &sender,
+        quirks,
+        sent: 0,
+        in_steps: false,
+        found: false,
+        in_result: false,
+        stopped: false,
+        error: None,
+    };
+    let mut de = serde_json::Deserializer::from_reader(BufReader::new(reader));
+
+    let outcome = match de.deserialize_map(EnvelopeVisitor { walk: &mut walk }) {
+        // the consumer went away, nobody is left to tell
+        Err(_) if walk.stopped => return,
+        Err(e) if walk.in_steps => Err(anyhow::Error::new(e).context(format!("malformed structLog at step {}", walk.sent))),
+        Err(e) => Err(anyhow::Error::new(e).context("invalid trace envelope")),
+        Ok(()) => match walk.error.take() {
+            Some(error) => Err(anyhow!("Rpc returned an error: {}", error)),
+            None if walk.found => de.end().context("trailing data after trace"),
+            None if walk.in_result => Err(anyhow!("result object has no 'structLogs' field")),
+            None => Err(anyhow!("Rpc response missing 'result' field")),
+        },
+    };
+    if let Err(e) = outcome {
+        let _ = sender.send(Err(e));
+    }
+}
+
+// what the visitors learned so far
+struct Walk<'a> {
+    sender: &'a SyncSender<Result<Instruction>>,
+    quirks: Quirks,
+    sent: u64,
+    in_steps: bool,
+    found: bool,
+    in_result: bool,
+    stopped: bool,              // the StructLogReader was dropped before the last step
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RpcErrorMessage {
+    #[serde(default)]
+    code: Option<i64>,
+    message: String,
+}
+
+impl fmt::Display for RpcErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} (code {})", self.message, code),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+// `{ ..., "result": { ..., "structLogs": [...] } }`, a bare result object is accepted as well
+struct EnvelopeVisitor<'w, 'a> {
+    walk: &'w mut Walk<'a>,
+}
+
+impl<'de> Visitor<'de> for EnvelopeVisitor<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a debug_traceTransaction response or struct logger result")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<(), A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "structLogs" => map.next_value_seed(StepsSeed { walk: &mut *self.walk })?,
+                "result" if !self.walk.in_result => {
+                    self.walk.in_result = true;
+                    map.next_value_seed(ResultSeed { walk: &mut *self.walk })?;
+                }
+                // some nodes send "error": null next to a result
+                "error" if !self.walk.in_result => {
+                    if let Some(error) = map.next_value::<Option<RpcErrorMessage>>()? {
+                        self.walk.error = Some(error.to_string());
+                    }
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct ResultSeed<'w, 'a> {
+    walk: &'w mut Walk<'a>,
+}
+
+impl<'de> DeserializeSeed<'de> for ResultSeed<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(EnvelopeVisitor { walk: self.walk })
+    }
+}
+
+struct StepsSeed<'w, 'a> {
+    walk: &'w mut Walk<'a>,
+}
+
+impl<'de> DeserializeSeed<'de> for StepsSeed<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for StepsSeed<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a structLogs list")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        self.walk.in_steps = true;
+        while let Some(raw) = seq.next_element::<RawStep>()? {
+            let step = self.walk.quirks.normalize(raw).map_err(de::Error::custom)?;
+            if self.walk.sender.send(Ok(step)).is_err() {
+                self.walk.stopped = true;
+                return Err(de::Error::custom("structLog reader dropped"));
+            }
+            self.walk.sent += 1;
+        }
+        self.walk.in_steps = false;
+        self.walk.found = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Opcode, Word};
+
+    const TRACE: &str = r#"
+    {
+        "jsonrpc": "2.0",
+        "id": 1,
+        "result": {
+            "gas": 21000,
+            "failed": false,
+            "returnValue": "",
+            "structLogs": [
+                { "pc": 0, "op": "PUSH1", "gas": 100, "gasCost": 3, "depth": 1, "stack": [] },
+                { "pc": 2, "op": "PUSH1", "gas": 97, "gasCost": 3, "depth": 1, "stack": ["0x80"] },
+                { "pc": 4, "op": "ADD", "gas": 94, "gasCost": 3, "depth": 1, "stack": ["0x80", "0x40"], "storage": { "0x00": "0x01" } },
+                { "pc": 5, "op": "STOP", "gas": 91, "gasCost": 0, "depth": 1, "stack": ["0xc0"] }
+            ]
+        }
+    }
+    "#;
+
+    #[test]
+    fn test_streams_every_step() {
+        let mut reader = StructLogReader::new(TRACE.as_bytes());
+        let steps: Vec<Instruction> = reader.by_ref().collect::<Result<_>>().unwrap();
+
+        assert_eq!(steps.len(), 4);
+        assert_eq!(reader.steps(), 4);
+        assert_eq!(steps[2].opcode, Opcode::ADD);
+        assert_eq!(steps[2].stack, vec![Word::from_u64(0x80), Word::from_u64(0x40)]);
+        assert_eq!(steps[3].opcode, Opcode::STOP);
+    }
+
+    #[test]
+    fn test_bare_result_and_empty_logs() {
+        let json = r#"{"gas": 0, "failed": false, "structLogs": []}"#;
+        let mut reader = StructLogReader::new(json.as_bytes());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_rpc_error_is_reported() {
+        let json = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"transaction not found"}}"#;
+        let mut reader = StructLogReader::new(json.as_bytes());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "Rpc returned an error: transaction not found (code -32000)");
+        assert!(reader.next().is_none());
+
+        // a callTracer result is not something we can stream steps from
+        let json = r#"{"jsonrpc":"2.0","id":1,"result":{"type":"CALL","calls":[]}}"#;
+        let err = StructLogReader::new(json.as_bytes()).next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "result object has no 'structLogs' field");
+    }
+
+    #[test]
+    fn test_truncated_trace_errors() {
+        let truncated = &TRACE[..TRACE.find("\"ADD\"").unwrap()];
+        let results: Vec<Result<Instruction>> = StructLogReader::new(truncated.as_bytes()).collect();
+
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok() && results[1].is_ok());
+        assert!(results[2].is_err());
+    }
+
+    // hands out the first bytes of a trace, then panics like a broken decompressor would
+    struct PanickingReader(&'static [u8]);
+
+    impl Read for PanickingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                panic!("decompressor blew up");
+            }
+            let n = buf.len().min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_reader_thread_panic_is_an_error() {
+        let start = &TRACE[..TRACE.find("\"ADD\"").unwrap()];
+        let results: Vec<Result<Instruction>> = StructLogReader::new(PanickingReader(start.as_bytes())).collect();
+
+        assert_eq!(results.len(), 3);
+        assert!(results[2].as_ref().unwrap_err().to_string().contains("panicked after step 2"));
+    }
+
+    #[test]
+    fn test_unknown_opcodes_are_counted_or_rejected() {
+        let json = r#"{"structLogs": [
+            { "pc": 0, "op": "PUSH0", "gas": 10, "depth": 1, "stack": [] },
+            { "pc": 1, "op": "FOO", "gas": 8, "depth": 1, "stack": ["0x0"] },
+            { "pc": 2, "op": 12, "gas": 8, "depth": 1, "stack": ["0x0"] },
+            { "pc": 3, "op": "OPCODE_NAME_FAR_LONGER_THAN_ANY_REAL_ONE", "gas": 8, "depth": 1, "stack": [] }
+        ]}"#;
+
+        let numeric_op = Quirks { numeric_op: true, ..Quirks::CANONICAL };
+        let mut reader = StructLogReader::new(json.as_bytes()).quirks(numeric_op);
+        let steps: Vec<Instruction> = reader.by_ref().collect::<Result<_>>().unwrap();
+        assert_eq!(reader.unknown_opcodes(), 3);
+        assert_eq!(reader.dropped_opcode_names(), 1);
+        assert_eq!(steps[1].opcode, Opcode::UnknownName("FOO"));
+        assert_eq!(steps[2].opcode, Opcode::Unknown(0x0c));
+        assert_eq!(steps[3].opcode, Opcode::UnknownNameOverflow);
+
+        let results: Vec<Result<Instruction>> = StructLogReader::new(json.as_bytes()).quirks(numeric_op).strict(true).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[1].as_ref().unwrap_err().to_string().contains("FOO"));
+
+        // a client that is not known to send op bytes
+        let results: Vec<Result<Instruction>> = StructLogReader::new(json.as_bytes()).collect();
+        assert_eq!(results.len(), 3);
+        assert!(format!("{:#}", results[2].as_ref().unwrap_err()).contains("numeric op is not sent by this client"));
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Deserializer};
+use serde::de::{self, IntoDeserializer, Visitor, SeqAccess, MapAccess};
+
+use crate::{Instruction, Opcode, Word};
+
+// How a client's struct logger output deviates from the canonical (geth) encoding.
+// trace-ir only knows the encodings, which client sends which is up to trace-rpc's adapters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    pub depth_base: u64,        // depth reported for the top-level frame
+    pub numeric_op: bool,       // op as the opcode byte instead of its name
+    pub memory_blob: bool,      // memory as one hex string instead of a list of 32 byte words
+    pub error_object: bool,     // error as {"message": ...} instead of a string
+}
+
+impl Quirks {
+    pub const CANONICAL: Quirks = Quirks {
+        depth_base: 1,
+        numeric_op: false,
+        memory_blob: false,
+        error_object: false,
+    };
+
+    // maps a raw structLog depth onto the canonical 1-based depth used by trace-ir
+    pub fn normalize_depth(&self, raw: u64) -> Option<u64> {
+        raw.checked_sub(self.depth_base).map(|d| d + 1)
+    }
+
+    // one structLogs element in canonical form, depth included
+    pub(crate) fn normalize(&self, raw: RawStep) -> Result<Instruction> {
+        let mut step = self.decode(raw)?;
+        step.depth = self.normalize_depth(step.depth)
+            .ok_or_else(|| anyhow!("depth {} is below the depth base {}", step.depth, self.depth_base))?;
+        Ok(step)
+    }
+
+    // encodings the client is not known to send are rejected, they mean the wrong client was assumed
+    pub(crate) fn decode(&self, raw: RawStep) -> Result<Instruction> {
+        Ok(Instruction {
+            pc: raw.pc,
+            opcode: raw.op.accept(self.numeric_op, "numeric op")?,
+            gas: raw.gas,
+            gas_cost: raw.gas_cost,
+            stack: raw.stack,
+            depth: raw.depth,
+            memory: raw.memory.accept(self.memory_blob, "memory as a hex string")?,
+            storage: raw.storage,
+            refund: raw.refund,
+            error: raw.error.accept(self.error_object, "error object")?,
+        })
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::CANONICAL
+    }
+}
+
+// a field value and whether the client sent it in the canonical encoding
+#[derive(Debug)]
+pub(crate) enum Encoded<T> {
+    Canonical(T),
+    Alternate(T),
+}
+
+impl<T> Encoded<T> {
+    fn accept(self, allowed: bool, what: &str) -> Result<T> {
+        match self {
+            Encoded::Canonical(value) => Ok(value),
+            Encoded::Alternate(value) if allowed => Ok(value),
+            Encoded::Alternate(_) => bail!("{} is not sent by this client, is the client flavor right?", what),
+        }
+    }
+}
+
+impl<T: Default> Default for Encoded<T> {
+    fn default() -> Self {
+        Encoded::Canonical(T::default())
+    }
+}
+
+// one structLogs element as the client encoded it
+#[derive(Deserialize)]
+pub(crate) struct RawStep {
+    pc: u64,
+    #[serde(deserialize_with = "op")]
+    op: Encoded<Opcode>,
+    gas: u64,
+    #[serde(rename = "gasCost")]
+    gas_cost: Option<u64>,
+    #[serde(default, deserialize_with = "stack")]
+    stack: Vec<Word>,
+    depth: u64,
+    #[serde(default, deserialize_with = "memory")]
+    memory: Encoded<Option<Vec<Word>>>,
+    #[serde(default)]
+    storage: Option<BTreeMap<Word, Word>>,
+    #[serde(default)]
+    refund: Option<u64>,
+    #[serde(default, deserialize_with = "step_error")]
+    error: Encoded<Option<String>>,
+}
+
+fn op<'de, D>(deserializer: D) -> Result<Encoded<Opcode>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(OpVisitor)
+}
+
+struct OpVisitor;
+
+impl<'de> Visitor<'de> for OpVisitor {
+    type Value = Encoded<Opcode>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an opcode name or byte")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        u8::try_from(v)
+            .map(|byte| Encoded::Alternate(Opcode::from_trace_byte(byte)))
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Opcode::deserialize(v.into_deserializer()).map(Encoded::Canonical)
+    }
+}
+
+// `null` (disableStack on some clients) is the same as an empty stack
+fn stack<'de, D>(deserializer: D) -> Result<Vec<Word>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<Vec<Word>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+// memory comes as a list of 32 byte words, or as one hex blob
+fn memory<'de, D>(deserializer: D) -> Result<Encoded<Option<Vec<Word>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(MemoryVisitor)
+}
+
+struct MemoryVisitor;
+
+impl<'de> Visitor<'de> for MemoryVisitor {
+    type Value = Encoded<Option<Vec<Word>>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of memory words or a hex string")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Encoded::Canonical(None))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Encoded::Canonical(None))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut words = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(word) = seq.next_element::<Word>()? {
+            words.push(word);
+        }
+        Ok(Encoded::Canonical(Some(words)))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        let digits = v.strip_prefix("0x").unwrap_or(v);
+        if !digits.len().is_multiple_of(64) {
+            return Err(E::invalid_length(digits.len(), &"a multiple of 32 bytes"));
+        }
+
+        digits
+            .as_bytes()
+            .chunks(64)
+            .map(|chunk| {
+                std::str::from_utf8(chunk).ok()
+                    .and_then(Word::from_hex)
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+            })
+            .collect::<Result<Vec<_>, E>>()
+            .map(|words| Encoded::Alternate(Some(words)))
+    }
+}
+
+// the step error is a string on geth, some clients send an object with a message, or null
+fn step_error<'de, D>(deserializer: D) -> Result<Encoded<Option<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(StepErrorVisitor)
+}
+
+struct StepErrorVisitor;
+
+impl<'de> Visitor<'de> for StepErrorVisitor {
+    type Value = Encoded<Option<String>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an error string or object")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Encoded::Canonical(None))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Encoded::Canonical(None))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        if v.is_empty() {
+            return Ok(Encoded::Canonical(None));
+        }
+        Ok(Encoded::Canonical(Some(v.to_string())))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut message = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "message" | "msg" | "error" => message = map.next_value::<Option<String>>()?,
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(Encoded::Alternate(Some(message.unwrap_or_else(|| "unknown error".to_string()))))
+    }
+}
@@ -0,0 +1,458 @@
+use std::fmt::Write;
+use alloy_primitives::{Address, U256};
+
+use crate::{CallFrame, CallType, Instruction, Word};
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+// Renders a call tree one line per frame:
+//
+//   TX 0x1f98…2d3e → 0xa0b8…eb48 gas 46109/50000 ✓
+//   ├─ CALL 0xa0b8…eb48 → 0x43b4…3d48 value=1 ETH gas 21000/40000 ✓
+//   └─ STATICCALL 0xa0b8…eb48 → 0x0000…0001 gas 3000/3000 ✗ out of gas
+pub struct TreeRenderer {
+    max_depth: Option<usize>,
+    color: bool,
+    step_counts: bool,
+    full_addresses: bool,
+}
+
+impl Default for TreeRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TreeRenderer {
+    pub fn new() -> Self {
+        Self {
+            max_depth: None,
+            color: false,
+            step_counts: false,
+            full_addresses: false,
+        }
+    }
+
+    // frames below this many levels under the root are summarized, not printed
+    pub fn max_depth(mut self, depth: Option<usize>) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    // ANSI colors: failed frames in red
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    // append the number of steps each frame executed itself
+    pub fn step_counts(mut self, step_counts: bool) -> Self {
+        self.step_counts = step_counts;
+        self
+    }
+
+    pub fn full_addresses(mut self, full: bool) -> Self {
+        self.full_addresses = full;
+        self
+    }
+
+    pub fn render(&self, root: &CallFrame) -> String {
+        let mut out = String::new();
+        self.render_frame(&mut out, root, "", "", 0);
+        out
+    }
+
+    // `lead` goes in front of this frame's line, `indent` in front of its children's
+    fn render_frame(&self, out: &mut String, frame: &CallFrame, lead: &str, indent: &str, depth: usize) {
+        out.push_str(lead);
+        self.write_line(out, frame);
+        out.push('\n');
+
+        if frame.children.is_empty() {
+            return;
+        }
+        if self.max_depth.is_some_and(|max| depth >= max) {
+            let hidden = count_frames(&frame.children);
+            let _ = writeln!(out, "{}└─ {}", indent, self.paint(DIM, &format!("… {} more frame(s)", hidden)));
+            return;
+        }
+
+        let last = frame.children.len() - 1;
+        for (i, child) in frame.children.iter().enumerate() {
+            let (branch, next) = if i == last { ("└─ ", "   ") } else { ("├─ ", "│  ") };
+            self.render_frame(
+                out,
+                child,
+                &format!("{}{}", indent, branch),
+                &format!("{}{}", indent, next),
+                depth + 1,
+            );
+        }
+    }
+
+    fn write_line(&self, out: &mut String, frame: &CallFrame) {
+        let mut line = format!(
+            "{} {} → {}",
+            call_label(&frame.call_type),
+            self.address(&frame.from),
+            self.address(&frame.to)
+        );
+        // annotated frames show what was called, the bare selector when nothing matched
+        if let Some(abi) = &frame.abi {
+            match &abi.function {
+                Some(function) => {
+                    let _ = write!(line, " {}", function);
+                }
+                None if frame.calldata.len() >= 4 => {
+                    let _ = write!(line, " 0x{}", hex::encode(&frame.calldata[..4]));
+                }
+                None => {}
+            }
+        }
+        if frame.value != Word::ZERO {
+            let _ = write!(line, " value={}", format_ether(frame.value.into()));
+        }
+        let _ = write!(line, " gas {}/{}", frame.gas_used, frame.gas_limit);
+        if self.step_counts {
+            let _ = write!(line, " steps={}", frame.step_count);
+        }
+
+        match (&frame.error, frame.success) {
+            (None, true) => line.push_str(" ✓"),
+            (Some(error), _) => {
+                let _ = write!(line, " ✗ {}", error);
+            }
+            (None, false) => line.push_str(" ✗"),
+        }
+        if let Some(revert) = frame.abi.as_ref().and_then(|abi| abi.revert.as_ref()) {
+            let _ = write!(line, ": {}", revert);
+        }
+
+        if frame.success {
+            out.push_str(&line);
+        } else {
+            out.push_str(&self.paint(RED, &line));
+        }
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        paint(self.color, color, text)
+    }
+
+    fn address(&self, address: &Address) -> String {
+        if self.full_addresses {
+            return address.to_checksum(None);
+        }
+        let hex = format!("{:x}", address);
+        format!("0x{}…{}", &hex[..4], &hex[hex.len() - 4..])
+    }
+}
+
+fn paint(enabled: bool, color: &str, text: &str) -> String {
+    if enabled {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+// Execution listing, one line per step plus one line per changed memory range:
+//
+//       pc  op                    gas   cost  depth  stack (top first)
+//       10  MSTORE               9979      3      1  [0x40*, 0x80]
+//       11  CALLVALUE            9976      2      1  []
+//           mem[0x5f..0x60] 0x80
+//
+// Steps must be fed in execution order, also the ones that are not printed (see skip),
+// since every line is diffed against the step right before it. A structLog shows the
+// state before its op runs, so the highlighted stack items and memory writes on a line
+// are the effect of the previous op. Changed stack items are green (or marked with *).
+pub struct StepFormatter {
+    stack_items: usize,
+    color: bool,
+    // what was last seen in every open frame by depth, a caller's entry is its CALL step until the callee returns
+    frames: Vec<Option<SeenStep>>,
+}
+
+// the parts of a step the next one is diffed against, buffers are reused so remembering a step does not allocate
+#[derive(Default)]
+struct SeenStep {
+    stack: Vec<Word>,
+    memory: Option<Vec<Word>>,
+}
+
+impl Default for StepFormatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StepFormatter {
+    pub fn new() -> Self {
+        Self {
+            stack_items: 4,
+            color: false,
+            frames: Vec::new(),
+        }
+    }
+
+    // how many items from the top of the stack to show
+    pub fn stack_items(mut self, n: usize) -> Self {
+        self.stack_items = n;
+        self
+    }
+
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn header(&self) -> String {
+        format!("{:>6}  {:<14} {:>10} {:>6} {:>6}  stack (top first)", "pc", "op", "gas", "cost", "depth")
+    }
+
+    // remembers a step without formatting it
+    pub fn skip(&mut self, step: &Instruction) {
+        self.remember(step);
+    }
+
+    pub fn format(&mut self, step: &Instruction) -> String {
+        // only the same frame's previous step is comparable, after a return that is the CALL step
+        let previous = self.frames.get(frame_index(step)).and_then(Option::as_ref);
+
+        let cost = step.gas_cost.map(|c| c.to_string()).unwrap_or_else(|| "-".to_string());
+        let mut line = format!(
+            "{:>6}  {:<14} {:>10} {:>6} {:>6}  {}",
+            step.pc,
+            step.opcode,
+            step.gas,
+            cost,
+            step.depth,
+            self.stack(step, previous)
+        );
+        if let Some(error) = &step.error {
+            line.push_str(&paint(self.color, RED, &format!("  error: {}", error)));
+        }
+
+        for (offset, bytes) in memory_writes(previous, step) {
+            let _ = write!(
+                line,
+                "\n{:>8}mem[{:#x}..{:#x}] 0x{}",
+                "",
+                offset,
+                offset + bytes.len(),
+                hex::encode(&bytes)
+            );
+        }
+
+        self.remember(step);
+        line
+    }
+
+    // frames deeper than the step have returned
+    fn remember(&mut self, step: &Instruction) {
+        let index = frame_index(step);
+        self.frames.truncate(index + 1);
+        self.frames.resize_with(index + 1, || None);
+        let seen = self.frames[index].get_or_insert_with(SeenStep::default);
+        seen.stack.clone_from(&step.stack);
+        seen.memory.clone_from(&step.memory);
+    }
+
+    fn stack(&self, step: &Instruction, previous: Option<&SeenStep>) -> String {
+        let len = step.stack.len();
+        let shown = self.stack_items.min(len);
+
+        // positions are counted from the bottom, that is what stays put between steps
+        let items: Vec<String> = (len - shown..len)
+            .rev()
+            .map(|i| {
+                let word = step.stack[i].to_string();
+                let changed = previous.is_some_and(|p| p.stack.get(i) != Some(&step.stack[i]));
+                match (changed, self.color) {
+                    (false, _) => word,
+                    (true, true) => paint(true, GREEN, &word),
+                    (true, false) => format!("{}*", word),
+                }
+            })
+            .collect();
+
+        let more = if len > shown { format!(" +{}", len - shown) } else { String::new() };
+        format!("[{}]{}", items.join(", "), more)
+    }
+}
+
+fn frame_index(step: &Instruction) -> usize {
+    step.depth.saturating_sub(1) as usize
+}
+
+// byte ranges of memory that differ from the previous step, as (offset, new bytes).
+// memory only grows with zeros, so bytes past the old end count as zero
+fn memory_writes(previous: Option<&SeenStep>, step: &Instruction) -> Vec<(usize, Vec<u8>)> {
+    let (Some(previous), Some(memory)) = (previous, &step.memory) else {
+        return Vec::new();
+    };
+    let before = previous.memory.as_deref().unwrap_or(&[]);
+
+    let mut writes: Vec<(usize, Vec<u8>)> = Vec::new();
+    for (i, word) in memory.iter().enumerate() {
+        let old = before.get(i).copied().unwrap_or(Word::ZERO);
+        if old == *word {
+            continue;
+        }
+        let (old, new) = (old.to_be_bytes(), word.to_be_bytes());
+        for pos in 0..32 {
+            if old[pos] == new[pos] {
+                continue;
+            }
+            let offset = i * 32 + pos;
+            match writes.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == offset => bytes.push(new[pos]),
+                _ => writes.push((offset, vec![new[pos]])),
+            }
+        }
+    }
+    writes
+}
+
+fn call_label(call_type: &CallType) -> &'static str {
+    match call_type {
+        CallType::Root => "TX",
+        CallType::Call => "CALL",
+        CallType::StaticCall => "STATICCALL",
+        CallType::DelegateCall => "DELEGATECALL",
+        CallType::CallCode => "CALLCODE",
+        CallType::Create => "CREATE",
+        CallType::Create2 => "CREATE2",
+        CallType::ExtCall => "EXTCALL",
+        CallType::ExtDelegateCall => "EXTDELEGATECALL",
+        CallType::ExtStaticCall => "EXTSTATICCALL",
+        CallType::EofCreate => "EOFCREATE",
+    }
+}
+
+fn count_frames(frames: &[CallFrame]) -> usize {
+    frames.iter().map(|f| 1 + count_frames(&f.children)).sum()
+}
+
+// wei as ETH without trailing zeros, e.g. 1 ETH, 0.25 ETH, 0.000000000000000001 ETH
+pub fn format_ether(wei: U256) -> String {
+    let unit = U256::from(10u64).pow(U256::from(18u64));
+    let whole = wei / unit;
+    let fraction = wei % unit;
+
+    if fraction == U256::ZERO {
+        return format!("{} ETH", whole);
+    }
+    let digits = format!("{:0>18}", fraction.to_string());
+    format!("{}.{} ETH", whole, digits.trim_end_matches('0'))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FrameError;
+
+    fn frame(call_type: CallType, to: u8, gas_used: u64) -> CallFrame {
+        let mut frame = CallFrame::new(call_type, Address::ZERO, Address::with_last_byte(to), 50000);
+        frame.gas_used = gas_used;
+        frame
+    }
+
+    #[test]
+    fn test_renders_tree() {
+        let mut root = frame(CallType::Root, 0xaa, 46109);
+        let mut call = frame(CallType::Call, 0xbb, 21000);
+        call.value = Word(U256::from(10u64).pow(U256::from(18u64)));
+        call.step_count = 12;
+        let mut failed = frame(CallType::StaticCall, 0x01, 3000);
+        failed.success = false;
+        failed.error = Some(FrameError::OutOfGas);
+        call.children.push(frame(CallType::DelegateCall, 0xcc, 10));
+        root.children.push(call);
+        root.children.push(failed);
+
+        let rendered = TreeRenderer::new().step_counts(true).render(&root);
+        assert_eq!(rendered, "\
+TX 0x0000…0000 → 0x0000…00aa gas 46109/50000 steps=0 ✓
+├─ CALL 0x0000…0000 → 0x0000…00bb value=1 ETH gas 21000/50000 steps=12 ✓
+│  └─ DELEGATECALL 0x0000…0000 → 0x0000…00cc gas 10/50000 steps=0 ✓
+└─ STATICCALL 0x0000…0000 → 0x0000…0001 gas 3000/50000 steps=0 ✗ out of gas
+");
+
+        let shallow = TreeRenderer::new().max_depth(Some(0)).color(true).render(&root);
+        assert_eq!(shallow.lines().nth(1), Some("└─ \x1b[2m… 3 more frame(s)\x1b[0m"));
+    }
+
+    #[test]
+    fn test_renders_decoded_calls() {
+        let mut root = frame(CallType::Root, 0xaa, 30000);
+        root.success = false;
+        root.error = Some(FrameError::Reverted);
+        // transfer(0x…bb, 5) reverting with Error("nope")
+        root.calldata = hex::decode(
+            "a9059cbb00000000000000000000000000000000000000000000000000000000000000bb\
+             0000000000000000000000000000000000000000000000000000000000000005",
+        )
+        .unwrap();
+        root.return_data = hex::decode(
+            "08c379a0000000000000000000000000000000000000000000000000000000000000002\
+             00000000000000000000000000000000000000000000000000000000000000004\
+             6e6f706500000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+        let mut unknown = frame(CallType::Call, 0xbb, 100);
+        unknown.calldata = vec![0xde, 0xad, 0xbe, 0xef, 0x01];
+        root.children.push(unknown);
+        crate::AbiRegistry::new().annotate(&mut root);
+
+        let rendered = TreeRenderer::new().full_addresses(true).render(&root);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert!(lines[0].contains(" transfer(0x00000000000000000000000000000000000000bb, 5) gas"), "{}", lines[0]);
+        assert!(lines[0].ends_with("✗ execution reverted: \"nope\""), "{}", lines[0]);
+        assert!(lines[1].contains(" 0xdeadbeef gas"), "{}", lines[1]);
+    }
+
+    fn step(json: &str) -> Instruction {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_formats_steps_with_diffs() {
+        let mut steps = StepFormatter::new().stack_items(2);
+        let first = step(r#"{"pc":0,"op":"PUSH1","gas":100,"gasCost":3,"depth":1,"stack":["0x1","0x2"],"memory":[]}"#);
+        let second = step(r#"{"pc":2,"op":"MSTORE","gas":97,"gasCost":6,"depth":1,"stack":["0x1","0x2","0x80"],
+            "memory":["0x00000000000000000000000000000000000000000000000000000000000000ff"]}"#);
+
+        assert_eq!(steps.format(&first), "     0  PUSH1                 100      3      1  [0x2, 0x1]");
+        assert_eq!(
+            steps.format(&second),
+            "     2  MSTORE                 97      6      1  [0x80*, 0x2] +1\n        mem[0x1f..0x20] 0xff"
+        );
+
+        // a new frame is not diffed against its caller
+        let callee = step(r#"{"pc":0,"op":"STOP","gas":50,"gasCost":0,"depth":2,"stack":["0x5"],"error":"boom"}"#);
+        assert_eq!(steps.format(&callee), "     0  STOP                   50      0      2  [0x5]  error: boom");
+
+        // after the return the caller is diffed against its CALL step, not the callee's last step
+        let resumed = step(r#"{"pc":3,"op":"POP","gas":90,"gasCost":2,"depth":1,"stack":["0x1","0x2","0x80","0x1"],
+            "memory":["0x00000000000000000000000000000000000000000000000000000000000000ff"]}"#);
+        assert_eq!(steps.format(&resumed), "     3  POP                    90      2      1  [0x1*, 0x80] +2");
+
+        // and the next call starts a fresh frame again
+        let next_callee = step(r#"{"pc":0,"op":"STOP","gas":40,"gasCost":0,"depth":2,"stack":["0x6"]}"#);
+        assert_eq!(steps.format(&next_callee), "     0  STOP                   40      0      2  [0x6]");
+    }
+
+    #[test]
+    fn test_format_ether() {
+        assert_eq!(format_ether(U256::ZERO), "0 ETH");
+        assert_eq!(format_ether(U256::from(250_000_000_000_000_000u64)), "0.25 ETH");
+        assert_eq!(format_ether(U256::from(1u64)), "0.000000000000000001 ETH");
+        assert_eq!(format_ether(U256::from(3_500_000_000_000_000_000u64)), "3.5 ETH");
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::{BTreeMap, BTreeSet};
+use alloy_primitives::Address;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use crate::{CallFrame, CallType, SlotWrite, Word};
+
+// What a transaction changed, per account, as far as the trace shows it.
+// Balances only move by transferred value: gas fees and whatever SELFDESTRUCT hands over
+// are not on the stack. TSTORE writes are left out, transient storage is gone once the tx ends.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDiff {
+    pub sender: Address,            // pays the gas, so its balance never matches value flows alone
+    pub accounts: BTreeMap<Address, AccountDiff>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountDiff {
+    pub received: Word,             // value transferred in
+    pub sent: Word,                 // value transferred out
+    pub nonce_delta: u64,
+    pub storage: BTreeMap<Word, SlotWrite>,
+    pub created: bool,              // code was deployed here by this tx
+    pub self_destructed: Option<Address>,   // beneficiary
+}
+
+impl StateDiff {
+    // the tree has to come from a builder seeded with_transaction, otherwise the root
+    // accounts are zero. `creates_contract` is for txs without a `to`, which a trace
+    // cannot tell apart from a call
+    pub fn from_call_tree(root: &CallFrame, creates_contract: bool) -> Self {
+        let mut diff = StateDiff { sender: root.from, accounts: BTreeMap::new() };
+
+        // the sender's nonce moves even when the tx reverts
+        diff.account(root.from).nonce_delta += 1;
+        if !root.success {
+            return diff;
+        }
+
+        diff.transfer(root.from, root.to, root.value);
+        if creates_contract {
+            let created = diff.account(root.to);
+            created.created = true;
+            created.nonce_delta += 1;
+        }
+        diff.record_frame(root);
+
+        // writes of failed frames are already rolled back in the root's view
+        for (address, slots) in &root.storage.writes {
+            for (slot, write) in slots {
+                if write.original != Some(write.new) {
+                    diff.account(*address).storage.insert(*slot, *write);
+                }
+            }
+        }
+        diff
+    }
+
+    fn account(&mut self, address: Address) -> &mut AccountDiff {
+        self.accounts.entry(address).or_default()
+    }
+
+    fn transfer(&mut self, from: Address, to: Address, value: Word) {
+        if value == Word::ZERO || from == to {
+            return;
+        }
+        let sender = self.account(from);
+        sender.sent = sender.sent.saturating_add(value);
+        let receiver = self.account(to);
+        receiver.received = receiver.received.saturating_add(value);
+    }
+
+    // `frame` succeeded, so did everything above it
+    fn record_frame(&mut self, frame: &CallFrame) {
+        if let Some(beneficiary) = frame.selfdestruct {
+            self.account(frame.storage_address).self_destructed = Some(beneficiary);
+        }
+
+        for child in &frame.children {
+            let creates = matches!(child.call_type, CallType::Create | CallType::Create2 | CallType::EofCreate);
+            // the creator's nonce is bumped before the init code runs, a callee that
+            // never ran failed one of the checks before that
+            if creates && (child.success || child.step_count > 0) {
+                self.account(frame.storage_address).nonce_delta += 1;
+            }
+            if !child.success {
+                continue;
+            }
+
+            match child.call_type {
+                // these keep the caller's context, nothing moves
+                CallType::CallCode | CallType::DelegateCall | CallType::ExtDelegateCall => {}
+                _ => self.transfer(child.from, child.storage_address, child.value),
+            }
+            if creates {
+                let created = self.account(child.storage_address);
+                created.created = true;
+                created.nonce_delta += 1;       // EIP-161, contracts start at nonce 1
+            }
+            self.record_frame(child);
+        }
+    }
+
+    // everything that disagrees with a prestateTracer diffMode response for the same tx.
+    // balances are only checked where the trace saw value move, the sender and
+    // SELFDESTRUCT parties are skipped (fees and amounts are not in the trace)
+    pub fn compare(&self, prestate: &PrestateDiff) -> Vec<DiffMismatch> {
+        let empty = PrestateAccount::default();
+        let no_change = AccountDiff::default();
+        let beneficiaries: BTreeSet<Address> = self.accounts.values().filter_map(|a| a.self_destructed).collect();
+
+        let addresses: BTreeSet<Address> = self.accounts.keys()
+            .chain(prestate.pre.keys())
+            .chain(prestate.post.keys())
+            .copied()
+            .collect();
+
+        let mut mismatches = Vec::new();
+        for address in addresses {
+            let ours = self.accounts.get(&address).unwrap_or(&no_change);
+            let pre = prestate.pre.get(&address).unwrap_or(&empty);
+            let post = prestate.post.get(&address).unwrap_or(&empty);
+
+            // diffMode only lists slots that changed, a cleared slot is missing from post
+            let slots: BTreeSet<Word> = ours.storage.keys()
+                .chain(pre.storage.keys())
+                .chain(post.storage.keys())
+                .copied()
+                .collect();
+            for slot in slots {
+                let write = ours.storage.get(&slot);
+                let theirs = post.storage.get(&slot).copied()
+                    .or_else(|| pre.storage.get(&slot).map(|_| Word::ZERO));
+                // a write of an unseen slot that the node calls unchanged rewrote the same value
+                if theirs.is_none() && write.is_some_and(|w| w.original.is_none()) {
+                    continue;
+                }
+                let ours = write.map(|w| w.new);
+                if ours != theirs {
+                    mismatches.push(DiffMismatch::Storage { address, slot, trace: ours, prestate: theirs });
+                }
+            }
+
+            let nonce_delta = match (pre.nonce, post.nonce) {
+                (_, None) => 0,
+                (pre, Some(post)) => post.saturating_sub(pre.unwrap_or(0)),
+            };
+            if nonce_delta != ours.nonce_delta {
+                mismatches.push(DiffMismatch::Nonce { address, trace: ours.nonce_delta, prestate: nonce_delta });
+            }
+
+            let has_code = |a: &PrestateAccount| a.code.as_deref().is_some_and(|c| !c.is_empty() && c != "0x");
+            let created = has_code(post) && !has_code(pre);
+            if created != ours.created {
+                mismatches.push(DiffMismatch::Created { address, trace: ours.created, prestate: created });
+            }
+
+            let moved = ours.received != Word::ZERO || ours.sent != Word::ZERO;
+            let skip_balance = address == self.sender
+                || ours.self_destructed.is_some()
+                || beneficiaries.contains(&address);
+            if moved && !skip_balance {
+                let before = pre.balance.unwrap_or(Word::ZERO);
+                let after = post.balance.unwrap_or(before);
+                let expected = before.wrapping_add(ours.received).wrapping_sub(ours.sent);
+                if expected != after {
+                    mismatches.push(DiffMismatch::Balance { address, trace: expected, prestate: after });
+                }
+            }
+        }
+        mismatches
+    }
+}
+
+// result of debug_traceTransaction with prestateTracer in diffMode: `pre` holds the old
+// values of everything touched, `post` only the fields that changed
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrestateDiff {
+    #[serde(default)]
+    pub pre: BTreeMap<Address, PrestateAccount>,
+    #[serde(default)]
+    pub post: BTreeMap<Address, PrestateAccount>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrestateAccount {
+    pub balance: Option<Word>,
+    pub nonce: Option<u64>,
+    pub code: Option<String>,
+    #[serde(default)]
+    pub storage: BTreeMap<Word, Word>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum DiffMismatch {
+    #[error("{address} slot {slot}: trace has {trace:?}, prestate has {prestate:?}")]
+    Storage { address: Address, slot: Word, trace: Option<Word>, prestate: Option<Word> },
+    #[error("{address} nonce: trace +{trace}, prestate +{prestate}")]
+    Nonce { address: Address, trace: u64, prestate: u64 },
+    #[error("{address} balance: trace expects {trace}, prestate has {prestate}")]
+    Balance { address: Address, trace: Word, prestate: Word },
+    #[error("{address} created: trace says {trace}, prestate says {prestate}")]
+    Created { address: Address, trace: bool, prestate: bool },
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StructLogReader;
+    use crate::analysis::CallTreeBuilder;
+
+    // A sends 3 wei to B, B writes slot 1, forwards 1 wei to C which reverts after
+    // writing, then CREATEs D which writes its slot 0
+    const TRACE: &str = r#"
+    {
+        "structLogs": [
+            { "pc": 0, "op": "SSTORE", "gas": 10000, "depth": 1, "stack": ["0x7", "0x1"] },
+            { "pc": 1, "op": "CALL", "gas": 9000, "depth": 1,
+              "stack": ["0x0", "0x0", "0x0", "0x0", "0x1", "0xcc", "0x1388"] },
+            { "pc": 0, "op": "SSTORE", "gas": 5000, "depth": 2, "stack": ["0x9", "0x2"] },
+            { "pc": 1, "op": "REVERT", "gas": 4000, "depth": 2, "stack": ["0x0", "0x0"] },
+            { "pc": 2, "op": "POP", "gas": 8000, "depth": 1, "stack": ["0x0"] },
+            { "pc": 3, "op": "CREATE", "gas": 7000, "depth": 1, "stack": ["0x0", "0x0", "0x0"] },
+            { "pc": 0, "op": "SSTORE", "gas": 3000, "depth": 2, "stack": ["0x5", "0x0"] },
+            { "pc": 1, "op": "STOP", "gas": 2000, "depth": 2, "stack": [] },
+            { "pc": 4, "op": "STOP", "gas": 1000, "depth": 1, "stack": ["0xdd"] }
+        ]
+    }
+    "#;
+
+    fn addr(b: u8) -> Address {
+        Address::with_last_byte(b)
+    }
+
+    fn diff() -> StateDiff {
+        let mut builder = CallTreeBuilder::new()
+            .with_transaction(addr(0xaa), addr(0xbb), Word::from_u64(3), Vec::new());
+        for step in StructLogReader::new(TRACE.as_bytes()) {
+            builder.push(step.unwrap()).unwrap();
+        }
+        StateDiff::from_call_tree(&builder.finish().unwrap(), false)
+    }
+
+    #[test]
+    fn test_builds_diff_with_rollback() {
+        let diff = diff();
+
+        assert_eq!(diff.accounts[&addr(0xaa)].nonce_delta, 1);
+        assert_eq!(diff.accounts[&addr(0xaa)].sent, Word::from_u64(3));
+        // the reverted call moved nothing and wrote nothing
+        assert!(!diff.accounts.contains_key(&addr(0xcc)));
+        assert_eq!(diff.accounts[&addr(0xbb)].received, Word::from_u64(3));
+        assert_eq!(diff.accounts[&addr(0xbb)].nonce_delta, 1);
+        assert_eq!(diff.accounts[&addr(0xbb)].storage[&Word::from_u64(1)].new, Word::from_u64(7));
+
+        let created = &diff.accounts[&addr(0xdd)];
+        assert!(created.created);
+        assert_eq!(created.nonce_delta, 1);
+        assert_eq!(created.storage[&Word::from_u64(0)].new, Word::from_u64(5));
+    }
+
+    #[test]
+    fn test_compares_against_prestate() {
+        let prestate: PrestateDiff = serde_json::from_str(r#"{
+            "pre": {
+                "0x00000000000000000000000000000000000000aa": { "balance": "0x100", "nonce": 4 },
+                "0x00000000000000000000000000000000000000bb": { "balance": "0x0", "nonce": 1, "code": "0x6000",
+                    "storage": { "0x0000000000000000000000000000000000000000000000000000000000000001": "0x0000000000000000000000000000000000000000000000000000000000000002" } },
+                "0x00000000000000000000000000000000000000c0": { "balance": "0x5" }
+            },
+            "post": {
+                "0x00000000000000000000000000000000000000aa": { "balance": "0x50", "nonce": 5 },
+                "0x00000000000000000000000000000000000000bb": { "balance": "0x3", "nonce": 2,
+                    "storage": { "0x0000000000000000000000000000000000000000000000000000000000000001": "0x0000000000000000000000000000000000000000000000000000000000000007" } },
+                "0x00000000000000000000000000000000000000dd": { "nonce": 1, "code": "0x00",
+                    "storage": { "0x0000000000000000000000000000000000000000000000000000000000000000": "0x0000000000000000000000000000000000000000000000000000000000000006" } },
+                "0x00000000000000000000000000000000000000c0": { "balance": "0x9" }
+            }
+        }"#).unwrap();
+
+        // the sender's fee and the coinbase tip are not the trace's business,
+        // only the slot value the node disagrees on is reported
+        assert_eq!(diff().compare(&prestate), vec![DiffMismatch::Storage {
+            address: addr(0xdd),
+            slot: Word::ZERO,
+            trace: Some(Word::from_u64(5)),
+            prestate: Some(Word::from_u64(6)),
+        }]);
+    }
+}
@@ -1,27 +1,302 @@
-use serde::{Serialize , Deserialize};
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::{self, Visitor};
 use std::fmt;
-use alloy_primitives::U256;
+use std::str::FromStr;
+use alloy_primitives::{Address, B256, I256, U256};
 
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Serialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[serde(transparent)]
 pub struct Word(pub U256);
 
+#[derive(Debug, thiserror::Error)]
+#[error("invalid hex word {0:?}")]
+pub struct ParseWordError(String);
+
 impl Word {
     pub const ZERO: Word = Word(U256::ZERO);
+    pub const MAX: Word = Word(U256::MAX);
 
     pub fn from_u64(a : u64)-> Self {
         Self(U256::from(a))
     }
+
+    // trace values are always hex, with or without 0x depending on the client
+    // (geth memory words and nethermind stacks come without it). short encodings
+    // like "0x0" and zero padding past 64 digits are both accepted, no digits at all is not
+    pub fn from_hex(s: &str) -> Option<Self> {
+        let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+        if digits.is_empty() {
+            return None;
+        }
+        let digits = digits.trim_start_matches('0');
+        if digits.is_empty() {
+            return Some(Word::ZERO);
+        }
+        U256::from_str_radix(digits, 16).ok().map(Word)
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        Self(U256::from_be_bytes(bytes))
+    }
+
+    // up to 32 bytes, left padded like a PUSH or a short return value
+    pub fn from_be_slice(bytes: &[u8]) -> Option<Self> {
+        U256::try_from_be_slice(bytes).map(Word)
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        self.0.to_be_bytes()
+    }
+
+    // the n-th byte counted from the most significant end, what BYTE and MSTORE see
+    pub fn byte(&self, n: usize) -> u8 {
+        if n >= 32 { 0 } else { self.0.byte(31 - n) }
+    }
+
+    // the low 20 bytes, how CALL and friends read their target operand
+    pub fn to_address(&self) -> Address {
+        Address::from_word(self.to_b256())
+    }
+
+    pub fn to_b256(&self) -> B256 {
+        B256::from(self.to_be_bytes())
+    }
+
+    // two's complement view for SDIV, SMOD, SLT, SGT and SAR
+    pub fn as_i256(&self) -> I256 {
+        I256::from_raw(self.0)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    // EVM truthiness, anything non-zero
+    pub fn as_bool(&self) -> bool {
+        !self.is_zero()
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        u64::try_from(self.0).ok()
+    }
+
+    // gas and counters, where anything past u64 is as good as infinite
+    pub fn saturating_to_u64(&self) -> u64 {
+        self.to_u64().unwrap_or(u64::MAX)
+    }
+
+    pub fn to_usize(&self) -> Option<usize> {
+        usize::try_from(self.0).ok()
+    }
+
+    // EVM arithmetic wraps mod 2^256
+    pub fn wrapping_add(self, other: Word) -> Word {
+        Word(self.0.wrapping_add(other.0))
+    }
+
+    pub fn wrapping_sub(self, other: Word) -> Word {
+        Word(self.0.wrapping_sub(other.0))
+    }
+
+    pub fn wrapping_mul(self, other: Word) -> Word {
+        Word(self.0.wrapping_mul(other.0))
+    }
+
+    pub fn saturating_add(self, other: Word) -> Word {
+        Word(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Word) -> Word {
+        Word(self.0.saturating_sub(other.0))
+    }
+
+    pub fn checked_sub(self, other: Word) -> Option<Word> {
+        self.0.checked_sub(other.0).map(Word)
+    }
+
+    // 0x-prefixed hex without leading zeros, long values elided as 0x1234…abcd
+    pub fn compact(&self) -> Compact {
+        Compact(*self)
+    }
 }
 
+impl From<u64> for Word {
+    fn from(value: u64) -> Self {
+        Self::from_u64(value)
+    }
+}
+
+impl From<bool> for Word {
+    fn from(value: bool) -> Self {
+        Self::from_u64(value as u64)
+    }
+}
+
+impl From<U256> for Word {
+    fn from(value: U256) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Word> for U256 {
+    fn from(word: Word) -> Self {
+        word.0
+    }
+}
+
+impl From<[u8; 32]> for Word {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self::from_be_bytes(bytes)
+    }
+}
+
+impl From<Word> for [u8; 32] {
+    fn from(word: Word) -> Self {
+        word.to_be_bytes()
+    }
+}
+
+impl From<B256> for Word {
+    fn from(value: B256) -> Self {
+        Self::from_be_bytes(value.0)
+    }
+}
+
+impl From<Word> for B256 {
+    fn from(word: Word) -> Self {
+        word.to_b256()
+    }
+}
+
+// left padded, the way addresses sit on the stack
+impl From<Address> for Word {
+    fn from(address: Address) -> Self {
+        Self::from(address.into_word())
+    }
+}
+
+impl From<I256> for Word {
+    fn from(value: I256) -> Self {
+        Self(value.into_raw())
+    }
+}
+
+impl From<Word> for I256 {
+    fn from(word: Word) -> Self {
+        word.as_i256()
+    }
+}
+
+impl FromStr for Word {
+    type Err = ParseWordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Word::from_hex(s).ok_or_else(|| ParseWordError(s.to_string()))
+    }
+}
+
+// unlike U256's own impl an unprefixed string is read as hex, never as decimal
+impl<'de> Deserialize<'de> for Word {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(WordVisitor)
+    }
+}
+
+struct WordVisitor;
+
+impl<'de> Visitor<'de> for WordVisitor {
+    type Value = Word;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a hex encoded 256-bit word")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(Word::from_u64(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Word::from_hex(v).ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+}
+
+// hex everywhere, words are hashes, addresses and slots far more often than amounts
 impl fmt::Debug for Word {
     fn fmt(&self, f: &mut fmt::Formatter<'_>)-> fmt::Result{
-        write!(f, "{}", self.0)
+        write!(f, "{:#x}", self.0)
     }
 }
 
 impl fmt::Display for Word {
     fn fmt(&self, f: &mut fmt::Formatter<'_>)-> fmt::Result{
-        write!(f, "{}", self.0)
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+impl fmt::LowerHex for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+impl fmt::UpperHex for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::UpperHex::fmt(&self.0, f)
+    }
+}
+
+pub struct Compact(Word);
+
+impl fmt::Display for Compact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = format!("{:x}", self.0.0);
+        if digits.len() <= 16 {
+            f.pad(&format!("0x{}", digits))
+        } else {
+            f.pad(&format!("0x{}…{}", &digits[..4], &digits[digits.len() - 4..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions_and_formatting() {
+        assert_eq!(Word::from_hex("ff"), Some(Word::from_u64(255)));
+        assert_eq!("0X0".parse::<Word>().unwrap(), Word::ZERO);
+        assert_eq!(Word::from_hex(&format!("0x{}1", "0".repeat(70))), Some(Word::from_u64(1)));
+        assert_eq!(Word::from_hex("0x0"), Some(Word::ZERO));
+        assert_eq!(Word::from_hex("0"), Some(Word::ZERO));
+        assert_eq!(Word::from_hex(""), None);
+        assert_eq!(Word::from_hex("0x"), None);
+        assert!("0xzz".parse::<Word>().is_err());
+
+        let address = Address::with_last_byte(0xaa);
+        let word = Word::from(address);
+        assert_eq!(word, Word::from_u64(0xaa));
+        assert_eq!(word.to_address(), address);
+        assert_eq!(B256::from(word), address.into_word());
+        assert_eq!(Word::from(word.to_be_bytes()), word);
+        assert_eq!(word.byte(31), 0xaa);
+        assert_eq!(word.byte(0), 0);
+        assert_eq!(Word::from_be_slice(&[0x12, 0x34]), Some(Word::from_u64(0x1234)));
+
+        assert_eq!(Word::MAX.as_i256(), I256::MINUS_ONE);
+        assert_eq!(Word::from(I256::MINUS_ONE), Word::MAX);
+        assert!(Word::from(true).as_bool() && !Word::from(false).as_bool());
+        assert_eq!(Word::ZERO.wrapping_sub(Word::from_u64(1)), Word::MAX);
+        assert_eq!(Word::MAX.saturating_to_u64(), u64::MAX);
+
+        assert_eq!(format!("{}", word), "0xaa");
+        assert_eq!(format!("{:?}", Word::ZERO), "0x0");
+        assert_eq!(format!("{:x}", word), "aa");
+        assert_eq!(format!("{:#X}", word), "0xAA");
+        assert_eq!(Word::from_u64(0x1234).compact().to_string(), "0x1234");
+        assert_eq!(Word::MAX.compact().to_string(), "0xffff…ffff");
     }
 }
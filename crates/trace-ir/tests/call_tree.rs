@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+use alloy_primitives::Address;
+use anyhow::Result;
+use trace_ir::{CallFrame, CallType, FrameError, Opcode, Quirks, StructLogReader, Word};
+use trace_ir::analysis::CallTreeBuilder;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+// erigon reports the top-level frame at depth 0
+const ERIGON: Quirks = Quirks { depth_base: 0, ..Quirks::CANONICAL };
+// nethermind sends op bytes, memory as one blob and error objects
+const NETHERMIND: Quirks = Quirks { numeric_op: true, memory_blob: true, error_object: true, ..Quirks::CANONICAL };
+
+fn build(name: &str, quirks: Quirks) -> Result<CallFrame> {
+    let mut builder = CallTreeBuilder::new();
+    for step in StructLogReader::open(&fixture(name))?.quirks(quirks) {
+        builder.push(step?)?;
+    }
+    builder.finish()
+}
+
+#[test]
+fn test_nested_calls() {
+    let root = build("nested_calls.json", Quirks::CANONICAL).unwrap();
+
+    assert_eq!(root.instructions.len(), 4);
+    assert_eq!(root.children.len(), 1);
+
+    let outer = &root.children[0];
+    assert_eq!(outer.to, Address::with_last_byte(0xaa));
+    assert_eq!(outer.instructions.len(), 4);
+    assert_eq!(outer.children.len(), 1);
+
+    let inner = &outer.children[0];
+    assert_eq!(inner.from, Address::with_last_byte(0xaa));
+    assert_eq!(inner.to, Address::with_last_byte(0xbb));
+    assert_eq!(inner.instructions.len(), 2);
+    assert!(inner.children.is_empty());
+
+    // steps after each return land back in the caller, not in the callee
+    assert!(root.instructions.iter().all(|i| i.depth == 1));
+    assert!(outer.instructions.iter().all(|i| i.depth == 2));
+    assert!(inner.instructions.iter().all(|i| i.depth == 3));
+}
+
+#[test]
+fn test_erigon_depth_base() {
+    let geth = build("nested_calls.json", Quirks::CANONICAL).unwrap();
+    let erigon = build("erigon_nested_calls.json", ERIGON).unwrap();
+
+    assert_eq!(erigon.children.len(), 1);
+    assert_eq!(erigon.children[0].children.len(), 1);
+    assert_eq!(erigon.instructions.len(), geth.instructions.len());
+    assert!(erigon.instructions.iter().all(|i| i.depth == 1));
+    assert!(erigon.children[0].children[0].instructions.iter().all(|i| i.depth == 3));
+}
+
+#[test]
+fn test_nethermind_encodings() {
+    let root = build("nethermind_nested_calls.json", NETHERMIND).unwrap();
+
+    // op bytes and unprefixed stack words come out canonical
+    assert_eq!(root.instructions[1].opcode, Opcode::CALL);
+    let outer = &root.children[0];
+    assert_eq!(outer.to, Address::with_last_byte(0xaa));
+
+    // the error object is the inner frame's halt reason
+    let inner = &outer.children[0];
+    assert_eq!(inner.to, Address::with_last_byte(0xbb));
+    assert_eq!(inner.instructions[1].opcode, Opcode::ADD);
+    assert_eq!(inner.instructions[1].error.as_deref(), Some("out of gas"));
+    assert!(!inner.success);
+    assert_eq!(inner.error, Some(FrameError::OutOfGas));
+
+    // the memory blob is split into words
+    assert_eq!(outer.instructions[2].memory, Some(vec![Word::from_u64(0x2a)]));
+    assert!(outer.success && root.success);
+}
+
+#[test]
+fn test_wrong_client_is_rejected() {
+    assert!(build("erigon_nested_calls.json", Quirks::CANONICAL).is_err());
+    assert!(build("nested_calls.json", ERIGON).is_err());
+    assert!(build("nethermind_nested_calls.json", Quirks::CANONICAL).is_err());
+}
+
+#[test]
+fn test_sibling_calls() {
+    let root = build("sibling_calls.json", Quirks::CANONICAL).unwrap();
+
+    let types: Vec<CallType> = root.children.iter().map(|f| f.call_type.clone()).collect();
+    assert_eq!(types, vec![CallType::Call, CallType::StaticCall, CallType::DelegateCall]);
+    assert!(root.children.iter().all(|f| f.children.is_empty()));
+
+    assert_eq!(root.children[0].instructions.len(), 1);
+    assert_eq!(root.children[1].instructions.len(), 3);
+    assert_eq!(root.children[2].instructions.len(), 1);
+    assert_eq!(root.instructions.len(), 4);
+}
+
+#[test]
+fn test_immediate_revert() {
+    let root = build("immediate_revert.json", Quirks::CANONICAL).unwrap();
+
+    assert_eq!(root.children.len(), 1);
+    let child = &root.children[0];
+    assert!(!child.success);
+    assert_eq!(child.error, Some(FrameError::Reverted));
+    assert_eq!(child.instructions.len(), 1);
+
+    // the revert does not leak into the caller
+    assert!(root.success);
+    assert_eq!(root.instructions.len(), 3);
+}
+
+#[test]
+fn test_gas_and_halts() {
+    let root = build("halts.json", Quirks::CANONICAL).unwrap();
+    assert_eq!(root.children.len(), 4);
+
+    // returned normally: gas left after STOP is handed back, refund kept
+    let ok = &root.children[0];
+    assert!(ok.success);
+    assert_eq!(ok.gas_limit, 10000);
+    assert_eq!(ok.gas_used, 5003);
+    assert_eq!(ok.gas_refund, 4800);
+    assert_eq!(ok.error, None);
+
+    // out of gas burns the whole allowance
+    let oog = &root.children[1];
+    assert!(!oog.success);
+    assert_eq!(oog.gas_used, oog.gas_limit);
+    assert_eq!(oog.error, Some(FrameError::OutOfGas));
+
+    // value transfer to an account without code never shows up as a depth change
+    let transfer = &root.children[2];
+    assert!(transfer.success);
+    assert!(transfer.instructions.is_empty());
+    assert_eq!(transfer.to, Address::with_last_byte(0xcc));
+    assert_eq!(transfer.value, Word::from_u64(1));
+    assert_eq!(transfer.gas_limit, 2300);
+    assert_eq!(transfer.gas_used, 0);
+
+    // last step is a plain JUMP and the caller saw 0 pushed back
+    let bad_jump = &root.children[3];
+    assert_eq!(bad_jump.call_type, CallType::StaticCall);
+    assert!(!bad_jump.success);
+    assert_eq!(bad_jump.error, Some(FrameError::InvalidJump));
+
+    assert!(!root.success);
+    assert_eq!(root.error, Some(FrameError::InvalidOpcode));
+    assert_eq!(root.gas_used, root.gas_limit);
+}
+
+#[test]
+fn test_depth_drop_skipping_a_frame_is_rejected() {
+    // the depth 2 frame never shows its own last step, so nothing is known about how it ended
+    let err = build("depth_drop.json", Quirks::CANONICAL).unwrap_err();
+    assert!(err.to_string().contains("depth dropped from 3 to 1"), "{}", err);
+}
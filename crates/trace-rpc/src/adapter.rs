@@ -0,0 +1,182 @@
+use serde::{Serialize, Deserialize};
+use trace_ir::Quirks;
+
+use crate::{TracerOptions, BlockId, debug_trace_payload, receipt_payload};
+use crate::transaction::transaction_payload;
+use crate::block::{block_payload, trace_block_payload};
+
+// node implementation that produced a trace, recorded in the metadata so the trace can be read back
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientFlavor {
+    #[default]
+    Geth,
+    Erigon,
+    Reth,
+    Nethermind,
+    Anvil,
+}
+
+impl ClientFlavor {
+    // as it appears in web3_clientVersion (lowercased) and in metadata.json
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientFlavor::Geth => "geth",
+            ClientFlavor::Erigon => "erigon",
+            ClientFlavor::Reth => "reth",
+            ClientFlavor::Nethermind => "nethermind",
+            ClientFlavor::Anvil => "anvil",
+        }
+    }
+
+    // how trace-ir has to read structLogs produced by this client
+    pub fn quirks(&self) -> Quirks {
+        adapter_for(*self).quirks()
+    }
+}
+
+// Everything client specific about talking to a node lives behind this trait.
+// Supporting a new client means a new adapter, the fetcher and trace-ir stay untouched.
+pub trait ClientAdapter: Send + Sync {
+    fn flavor(&self) -> ClientFlavor;
+
+    // how this client's structLogs deviate from geth's, trace-ir normalizes them away
+    fn quirks(&self) -> Quirks {
+        Quirks::CANONICAL
+    }
+
+    // does a web3_clientVersion string belong to this client
+    fn matches(&self, client_version: &str) -> bool;
+
+    fn trace_transaction_payload(&self, tx_hash: &str, tracer: &TracerOptions) -> String {
+        debug_trace_payload(tx_hash, tracer)
+    }
+
+    fn receipt_payload(&self, tx_hash: &str) -> String {
+        receipt_payload(tx_hash)
+    }
+
+    fn transaction_payload(&self, tx_hash: &str) -> String {
+        transaction_payload(tx_hash)
+    }
+
+    fn block_payload(&self, block: &BlockId) -> String {
+        block_payload(block)
+    }
+
+    fn trace_block_payload(&self, block: &BlockId, tracer: &TracerOptions) -> String {
+        trace_block_payload(block, tracer)
+    }
+}
+
+// geth and the clients that speak its dialect unchanged (reth, anvil),
+// only the recorded flavor differs
+pub struct GethAdapter(pub ClientFlavor);
+pub struct ErigonAdapter;
+pub struct NethermindAdapter;
+
+// client version strings look like "Geth/v1.13.5-stable/linux-amd64/go1.21.4"
+fn client_name(client_version: &str) -> String {
+    client_version.split('/').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+impl ClientAdapter for GethAdapter {
+    fn flavor(&self) -> ClientFlavor {
+        self.0
+    }
+
+    fn matches(&self, client_version: &str) -> bool {
+        client_name(client_version) == self.0.name()
+    }
+}
+
+impl ClientAdapter for ErigonAdapter {
+    fn flavor(&self) -> ClientFlavor {
+        ClientFlavor::Erigon
+    }
+
+    // the top-level frame is at depth 0
+    fn quirks(&self) -> Quirks {
+        Quirks { depth_base: 0, ..Quirks::CANONICAL }
+    }
+
+    fn matches(&self, client_version: &str) -> bool {
+        client_name(client_version) == self.flavor().name()
+    }
+}
+
+impl ClientAdapter for NethermindAdapter {
+    fn flavor(&self) -> ClientFlavor {
+        ClientFlavor::Nethermind
+    }
+
+    // op as its byte, memory as one hex blob and the step error as an object
+    fn quirks(&self) -> Quirks {
+        Quirks {
+            numeric_op: true,
+            memory_blob: true,
+            error_object: true,
+            ..Quirks::CANONICAL
+        }
+    }
+
+    fn matches(&self, client_version: &str) -> bool {
+        client_name(client_version) == self.flavor().name()
+    }
+}
+
+pub fn adapter_for(flavor: ClientFlavor) -> Box<dyn ClientAdapter> {
+    match flavor {
+        ClientFlavor::Erigon => Box::new(ErigonAdapter),
+        ClientFlavor::Nethermind => Box::new(NethermindAdapter),
+        ClientFlavor::Geth
+        | ClientFlavor::Reth
+        | ClientFlavor::Anvil => Box::new(GethAdapter(flavor)),
+    }
+}
+
+// picks the adapter for a web3_clientVersion string,
+// unknown clients (hosted gateways etc.) are assumed to speak geth's dialect
+pub fn detect_adapter(client_version: &str) -> Box<dyn ClientAdapter> {
+    [ClientFlavor::Geth, ClientFlavor::Erigon, ClientFlavor::Reth, ClientFlavor::Nethermind, ClientFlavor::Anvil]
+        .into_iter()
+        .map(adapter_for)
+        .find(|adapter| adapter.matches(client_version))
+        .unwrap_or_else(|| Box::new(GethAdapter(ClientFlavor::Geth)))
+}
+
+pub fn client_version_payload() -> String {
+    r#"{"jsonrpc":"2.0","id":1,"method":"web3_clientVersion","params":[]}"#.to_string()
+}
+
+pub fn chain_id_payload() -> String {
+    r#"{"jsonrpc":"2.0","id":1,"method":"eth_chainId","params":[]}"#.to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_clients() {
+        assert_eq!(detect_adapter("Geth/v1.13.5-stable/linux-amd64/go1.21.4").flavor(), ClientFlavor::Geth);
+        assert_eq!(detect_adapter("erigon/2.55.1/linux-amd64/go1.20.7").flavor(), ClientFlavor::Erigon);
+        assert_eq!(detect_adapter("reth/v0.1.0-alpha.10-d1b4f5e/x86_64-unknown-linux-gnu").flavor(), ClientFlavor::Reth);
+        assert_eq!(detect_adapter("Nethermind/v1.25.4+20b10b35/linux-x64/dotnet8.0.2").flavor(), ClientFlavor::Nethermind);
+        assert_eq!(detect_adapter("anvil/v0.2.0").flavor(), ClientFlavor::Anvil);
+        assert_eq!(detect_adapter("Tenderly/1.0").flavor(), ClientFlavor::Geth);
+    }
+
+    #[test]
+    fn test_client_quirks() {
+        assert_eq!(ClientFlavor::Geth.quirks(), Quirks::CANONICAL);
+        assert_eq!(ClientFlavor::Erigon.quirks().depth_base, 0);
+        assert_eq!(ClientFlavor::Erigon.quirks().normalize_depth(0), Some(1));
+        assert!(ClientFlavor::Nethermind.quirks().numeric_op);
+        assert!(ClientFlavor::Nethermind.quirks().memory_blob);
+        assert!(ClientFlavor::Nethermind.quirks().error_object);
+        assert_eq!(ClientFlavor::Reth.quirks(), Quirks::CANONICAL);
+        assert_eq!(ClientFlavor::Anvil.quirks(), Quirks::CANONICAL);
+    }
+}
@@ -0,0 +1,329 @@
+use core::fmt;
+use std::str::FromStr;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context, anyhow, bail};
+use serde::Deserialize;
+use trace_ir::{Compression, open_artifact};
+
+use crate::{RpcRequest, TracerOptions, partial_path};
+use crate::store::{METADATA_FILE, TRACE_FILE, check_tx_hash};
+use crate::error::{RpcError, RpcErrorObject};
+use crate::compression::ArtifactWriter;
+use crate::scanner::JsonScanner;
+
+// block selector for block level acquisition
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockId {
+    Number(u64),
+    Hash(String),
+}
+
+impl BlockId {
+    fn rpc_param(&self) -> String {
+        match self {
+            BlockId::Number(n) => format!("0x{:x}", n),
+            BlockId::Hash(h) => h.clone(),
+        }
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockId::Number(n) => write!(f, "{}", n),
+            BlockId::Hash(h) => write!(f, "{}", h),
+        }
+    }
+}
+
+// decimal or 0x hex block number, or a 32 byte 0x block hash
+impl FromStr for BlockId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix("0x") {
+            Some(digits) if digits.len() == 64 => {
+                if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    bail!("invalid block hash {:?}", s);
+                }
+                Ok(BlockId::Hash(s.to_string()))
+            }
+            Some(digits) => u64::from_str_radix(digits, 16)
+                .map(BlockId::Number)
+                .with_context(|| format!("invalid block number {:?}", s)),
+            None => s.parse()
+                .map(BlockId::Number)
+                .with_context(|| format!("invalid block number {:?}", s)),
+        }
+    }
+}
+
+// eth_getBlockBy*, without full tx objects
+pub fn block_payload(block: &BlockId) -> String {
+    let method = match block {
+        BlockId::Number(_) => "eth_getBlockByNumber",
+        BlockId::Hash(_) => "eth_getBlockByHash",
+    };
+    let request = RpcRequest {
+        jsonrpc: "2.0",
+        id: 1,
+        method,
+        params: (block.rpc_param(), false),
+    };
+    serde_json::to_string(&request).expect("block request is always serializable")
+}
+
+pub fn trace_block_payload(block: &BlockId, tracer: &TracerOptions) -> String {
+    let method = match block {
+        BlockId::Number(_) => "debug_traceBlockByNumber",
+        BlockId::Hash(_) => "debug_traceBlockByHash",
+    };
+    let request = RpcRequest {
+        jsonrpc: "2.0",
+        id: 1,
+        method,
+        params: (block.rpc_param(), tracer.params()),
+    };
+    serde_json::to_string(&request).expect("block trace request is always serializable")
+}
+
+// the part of eth_getBlockBy* we need: tx hashes in execution order
+#[derive(Deserialize)]
+pub(crate) struct BlockTransactions {
+    pub transactions: Vec<String>,
+}
+
+// Splits a saved debug_traceBlockBy* response into one trace.json per tx under out_dir/<tx_hash>/.
+// Each per-tx result is copied byte for byte into the same JSON-RPC envelope
+// debug_traceTransaction would have produced, so validation and trace-ir read it the same way.
+// Entries of txs in `keep` are skipped so their cached files stay untouched.
+// Returns one entry per tx, in block order.
+pub(crate) fn split_block_trace(
+    block_file: &Path,
+    tx_hashes: &[String],
+    keep: &BTreeSet<String>,
+    out_dir: &Path,
+    compression: Compression,
+) -> Result<Vec<Result<PathBuf>>> {
+    // the hashes come from the node and become directory names, nothing else may reach the path
+    for tx_hash in tx_hashes {
+        check_tx_hash(tx_hash)?;
+    }
+
+    let file = open_artifact(block_file).context("could not open block trace")?;
+    let mut splitter = Splitter { scanner: JsonScanner::new(BufReader::new(file)), compression };
+
+    splitter.seek_result_array()?;
+
+    let mut traces = Vec::with_capacity(tx_hashes.len());
+    let mut first = true;
+
+    loop {
+        if splitter.scanner.eat(b']')? {
+            break;
+        }
+        if !first {
+            splitter.scanner.expect(b',')?;
+        }
+        first = false;
+
+        let index = traces.len();
+        let tx_hash = tx_hashes.get(index)
+            .ok_or_else(|| anyhow!("block trace has more entries than the block has transactions"))?;
+        traces.push(splitter.split_entry(tx_hash, &out_dir.join(tx_hash), keep.contains(tx_hash)));
+    }
+
+    if traces.len() != tx_hashes.len() {
+        bail!("block trace has {} entries but the block has {} transactions", traces.len(), tx_hashes.len());
+    }
+
+    Ok(traces)
+}
+
+// the block trace envelope, split entry by entry as it is read
+struct Splitter {
+    scanner: JsonScanner<BufReader<Box<dyn Read + Send>>>,
+    compression: Compression,   // for the per-tx files
+}
+
+impl Splitter {
+    // walks `{ ..., "result": [` and stops right after the '['
+    fn seek_result_array(&mut self) -> Result<()> {
+        let scanner = &mut self.scanner;
+        scanner.expect(b'{')?;
+        loop {
+            scanner.skip_whitespace()?;
+            if scanner.peek()? == Some(b'}') {
+                bail!("Rpc response missing 'result' field");
+            }
+
+            let key = scanner.read_string()?;
+            scanner.expect(b':')?;
+            match key.as_str() {
+                "result" => {
+                    if !scanner.eat(b'[')? {
+                        bail!("block trace 'result' is not a list");
+                    }
+                    return Ok(());
+                }
+                "error" => {
+                    let error: Option<RpcErrorObject> = serde_json::from_slice(&scanner.read_raw()?)
+                        .context("Invalid Rpc error object")?;
+                    if let Some(err) = error {
+                        return Err(anyhow::Error::new(RpcError::from(err)));
+                    }
+                }
+                _ => scanner.skip_value()?,
+            }
+
+            scanner.eat(b',')?;
+        }
+    }
+
+    // one `{"txHash": ..., "result": {...}}` entry, the outer Result is only for a broken stream
+    // with `skip` the result is only walked over, the trace already on disk is kept
+    fn split_entry(&mut self, tx_hash: &str, tx_dir: &Path, skip: bool) -> Result<PathBuf> {
+        let scanner = &mut self.scanner;
+        let trace_path = tx_dir.join(TRACE_FILE);
+        if skip {
+            scanner.skip_value()?;
+            return Ok(trace_path);
+        }
+        scanner.expect(b'{')?;
+
+        let mut written = false;
+        let mut reported_hash: Option<String> = None;
+        let mut error: Option<String> = None;
+
+        loop {
+            if scanner.eat(b'}')? {
+                break;
+            }
+
+            let key = scanner.read_string()?;
+            scanner.expect(b':')?;
+            match key.as_str() {
+                "result" => {
+                    fs::create_dir_all(tx_dir).context("Failed to create tx directory")?;
+                    // stale metadata must not vouch for the trace about to be replaced
+                    match fs::remove_file(tx_dir.join(METADATA_FILE)) {
+                        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                            return Err(e).context("could not remove stale metadata");
+                        }
+                        _ => {}
+                    }
+                    // like a download, the trace only appears under its name once it is complete
+                    let part_path = partial_path(&trace_path);
+                    let copied = ArtifactWriter::create(&part_path, self.compression)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|mut out| {
+                            out.write_all(br#"{"jsonrpc":"2.0","id":1,"result":"#)?;
+                            scanner.copy_value(Some(&mut out))?;
+                            out.write_all(b"}")?;
+                            out.finish()?;
+                            Ok(())
+                        });
+                    if let Err(e) = copied {
+                        let _ = fs::remove_file(&part_path);
+                        return Err(e);
+                    }
+                    fs::rename(&part_path, &trace_path).context("could not move split trace into place")?;
+                    written = true;
+                }
+                "txHash" => reported_hash = Some(scanner.read_string()?),
+                "error" => error = Some(String::from_utf8_lossy(&scanner.read_raw()?).into_owned()),
+                _ => scanner.skip_value()?,
+            }
+
+            scanner.eat(b',')?;
+        }
+
+        if let Some(reported) = reported_hash
+            && !reported.eq_ignore_ascii_case(tx_hash)
+        {
+            let _ = fs::remove_file(&trace_path);
+            bail!("block trace entry is for {} but the block lists {} at this position", reported, tx_hash);
+        }
+        if let Some(error) = error {
+            let _ = fs::remove_file(&trace_path);
+            bail!("node could not trace {} in block: {}", tx_hash, error);
+        }
+        if !written {
+            bail!("block trace entry for {} has no result", tx_hash);
+        }
+
+        Ok(trace_path)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splits_block_trace() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let [aa, bb, cc] = ["aa", "bb", "cc"].map(|b| format!("0x{}", b.repeat(32)));
+
+        let block = dir.join("block.json");
+        fs::write(&block, r#"{"jsonrpc":"2.0","id":1,"result":[
+            {"txHash":"AA","result":{"gas":1,"failed":false,"returnValue":"","structLogs":[{"op":"STOP","note":"]}\""}]}},
+            {"txHash":"BB","error":"execution timeout"},
+            {"result":{"gas":2,"failed":true,"returnValue":"","structLogs":[]}}
+        ]}"#.replace("AA", &aa).replace("BB", &bb)).unwrap();
+
+        // metadata of an earlier fetch must not survive the new trace
+        fs::create_dir_all(dir.join(&aa)).unwrap();
+        fs::write(dir.join(&aa).join(METADATA_FILE), "{}").unwrap();
+
+        let hashes = vec![aa.clone(), bb.clone(), cc.clone()];
+        let traces = split_block_trace(&block, &hashes, &BTreeSet::new(), dir, Compression::None).unwrap();
+
+        assert_eq!(traces.len(), 3);
+        let first = fs::read_to_string(traces[0].as_ref().unwrap()).unwrap();
+        assert_eq!(
+            first,
+            r#"{"jsonrpc":"2.0","id":1,"result":{"gas":1,"failed":false,"returnValue":"","structLogs":[{"op":"STOP","note":"]}\""}]}}"#
+        );
+        assert!(!dir.join(&aa).join(METADATA_FILE).exists());
+        assert!(!partial_path(&dir.join(&aa).join(TRACE_FILE)).exists());
+        assert!(traces[1].is_err());
+        assert!(traces[2].as_ref().unwrap().ends_with(format!("{}/trace.json", cc)));
+
+        // a kept entry is not rewritten, even when the node could not trace it this time
+        fs::write(dir.join(&aa).join(TRACE_FILE), "cached").unwrap();
+        let keep = BTreeSet::from([aa.clone(), bb.clone()]);
+        let traces = split_block_trace(&block, &hashes, &keep, dir, Compression::None).unwrap();
+        assert!(traces.iter().all(Result::is_ok));
+        assert_eq!(fs::read_to_string(dir.join(&aa).join(TRACE_FILE)).unwrap(), "cached");
+
+        // hashes from the node never become paths outside out_dir
+        let hostile = vec![aa.clone(), "../escaped".to_string(), cc.clone()];
+        assert!(split_block_trace(&block, &hostile, &BTreeSet::new(), dir, Compression::None).is_err());
+        assert!(!dir.join("../escaped").exists());
+    }
+
+    #[test]
+    fn test_parses_block_ids() {
+        assert_eq!("255".parse::<BlockId>().unwrap(), BlockId::Number(255));
+        assert_eq!("0xff".parse::<BlockId>().unwrap(), BlockId::Number(255));
+        let hash = format!("0x{}", "ab".repeat(32));
+        assert_eq!(hash.parse::<BlockId>().unwrap(), BlockId::Hash(hash.clone()));
+        assert!("latest".parse::<BlockId>().is_err());
+    }
+
+    #[test]
+    fn test_block_payloads() {
+        assert_eq!(
+            block_payload(&BlockId::Number(255)),
+            r#"{"jsonrpc":"2.0","id":1,"method":"eth_getBlockByNumber","params":["0xff",false]}"#
+        );
+        assert!(trace_block_payload(&BlockId::Hash("0xabc".into()), &TracerOptions::default())
+            .starts_with(r#"{"jsonrpc":"2.0","id":1,"method":"debug_traceBlockByHash","params":["0xabc",{"#));
+    }
+}
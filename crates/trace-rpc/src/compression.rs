@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+use tokio::io::{AsyncWrite, BufWriter as AsyncBufWriter};
+use trace_ir::Compression;
+
+// wraps a download target so the HTTP body is compressed while it streams in.
+// callers must `shutdown()` it, that is what writes the encoder trailer
+pub(crate) fn async_writer(file: tokio::fs::File, compression: Compression) -> Box<dyn AsyncWrite + Unpin + Send> {
+    let file = AsyncBufWriter::new(file);
+    match compression {
+        Compression::None => Box::new(file),
+        Compression::Zstd => Box::new(ZstdEncoder::new(file)),
+        Compression::Gzip => Box::new(GzipEncoder::new(file)),
+    }
+}
+
+// blocking counterpart, used when artifacts are written from local data (block splits)
+pub(crate) enum ArtifactWriter {
+    Plain(BufWriter<File>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+    Gzip(flate2::write::GzEncoder<BufWriter<File>>),
+}
+
+impl ArtifactWriter {
+    pub(crate) fn create(path: &Path, compression: Compression) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match compression {
+            Compression::None => ArtifactWriter::Plain(file),
+            Compression::Zstd => ArtifactWriter::Zstd(zstd::Encoder::new(file, 0)?),
+            Compression::Gzip => ArtifactWriter::Gzip(flate2::write::GzEncoder::new(file, flate2::Compression::default())),
+        })
+    }
+
+    // writes the encoder trailer and flushes, dropping without this leaves a truncated file
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self {
+            ArtifactWriter::Plain(mut file) => file.flush(),
+            ArtifactWriter::Zstd(encoder) => encoder.finish()?.flush(),
+            ArtifactWriter::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for ArtifactWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ArtifactWriter::Plain(w) => w.write(buf),
+            ArtifactWriter::Zstd(w) => w.write(buf),
+            ArtifactWriter::Gzip(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ArtifactWriter::Plain(w) => w.flush(),
+            ArtifactWriter::Zstd(w) => w.flush(),
+            ArtifactWriter::Gzip(w) => w.flush(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_writers_roundtrip() {
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"structLogs":[]}}"#;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        for compression in [Compression::None, Compression::Zstd, Compression::Gzip] {
+            let streamed = dir.join(format!("{:?}-async", compression));
+            let mut out = async_writer(tokio::fs::File::create(&streamed).await.unwrap(), compression);
+            // split like HTTP chunks would be
+            out.write_all(&body[..10]).await.unwrap();
+            out.write_all(&body[10..]).await.unwrap();
+            out.shutdown().await.unwrap();
+
+            let written = dir.join(format!("{:?}-sync", compression));
+            let mut out = ArtifactWriter::create(&written, compression).unwrap();
+            out.write_all(body).unwrap();
+            out.finish().unwrap();
+
+            for path in [streamed, written] {
+                let mut bytes = Vec::new();
+                trace_ir::open_artifact(&path).unwrap().read_to_end(&mut bytes).unwrap();
+                assert_eq!(bytes, body, "{:?}", compression);
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use thiserror::Error;
+
+// JSON-RPC error object as sent by the node
+#[derive(Debug, Deserialize)]
+pub(crate) struct RpcErrorObject {
+    pub code: i64,
+    pub message: String,
+    #[serde(default)]
+    pub data: Option<Box<RawValue>>,
+}
+
+// errors a node reports inside an otherwise fine HTTP response.
+// surfaced through anyhow, callers branch with `err.downcast_ref::<RpcError>()`
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RpcError {
+    #[error("transaction not found: {message}")]
+    TransactionNotFound { message: String },
+
+    // pruned node, needs an archive node for this block
+    #[error("historical state unavailable: {message}")]
+    StateUnavailable { message: String },
+
+    #[error("tracer timed out: {message}")]
+    TracerTimeout { message: String },
+
+    #[error("method not supported: {message}")]
+    MethodNotFound { message: String },
+
+    #[error("rpc error {code}: {message}")]
+    Other { code: i64, message: String, data: Option<String> },
+}
+
+// JSON-RPC 2.0 reserved code for unknown methods
+const METHOD_NOT_FOUND: i64 = -32601;
+
+impl RpcError {
+    // clients agree on little beyond the reserved codes, so the message decides
+    pub fn classify(code: i64, message: String, data: Option<String>) -> Self {
+        let m = message.to_ascii_lowercase();
+
+        if code == METHOD_NOT_FOUND || m.contains("method not found") || m.contains("does not exist/is not available") {
+            RpcError::MethodNotFound { message }
+        } else if m.contains("transaction") && m.contains("not found") {
+            RpcError::TransactionNotFound { message }
+        } else if m.contains("missing trie node")
+            || m.contains("historical state")
+            || m.contains("state is not available")
+            || m.contains("state not available")
+            || m.contains("pruned")
+        {
+            RpcError::StateUnavailable { message }
+        } else if m.contains("timeout") || m.contains("timed out") {
+            RpcError::TracerTimeout { message }
+        } else {
+            RpcError::Other { code, message, data }
+        }
+    }
+
+    pub fn is_state_unavailable(&self) -> bool {
+        matches!(self, RpcError::StateUnavailable { .. })
+    }
+}
+
+impl From<RpcErrorObject> for RpcError {
+    fn from(obj: RpcErrorObject) -> Self {
+        RpcError::classify(obj.code, obj.message, obj.data.map(|d| d.get().to_string()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> RpcError {
+        serde_json::from_str::<RpcErrorObject>(json).unwrap().into()
+    }
+
+    #[test]
+    fn test_classifies_node_errors() {
+        assert!(matches!(
+            parse(r#"{"code":-32000,"message":"transaction 0xabc not found"}"#),
+            RpcError::TransactionNotFound { .. }
+        ));
+        assert!(parse(r#"{"code":-32000,"message":"required historical state unavailable (reexec=128)"}"#).is_state_unavailable());
+        assert!(parse(r#"{"code":-32000,"message":"missing trie node 1a2b (path ) state 0x1a2b is not available"}"#).is_state_unavailable());
+        assert!(matches!(
+            parse(r#"{"code":-32000,"message":"execution timeout"}"#),
+            RpcError::TracerTimeout { .. }
+        ));
+        assert!(matches!(
+            parse(r#"{"code":-32601,"message":"the method debug_traceTransaction does not exist/is not available"}"#),
+            RpcError::MethodNotFound { .. }
+        ));
+    }
+
+    #[test]
+    fn test_keeps_unknown_errors() {
+        let err = parse(r#"{"code":3,"message":"execution reverted","data":"0x08c379a0"}"#);
+        assert_eq!(
+            err,
+            RpcError::Other { code: 3, message: "execution reverted".into(), data: Some(r#""0x08c379a0""#.into()) }
+        );
+    }
+}
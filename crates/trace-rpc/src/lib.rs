@@ -1,11 +1,40 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
 use std::path::{Path,PathBuf};
-use anyhow::{Result, Context};
-use serde_json::json;
-use reqwest::Client;
+use anyhow::{Result, Context, bail};
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use reqwest::{Client, RequestBuilder};
+use futures::stream::{self, StreamExt};
 use tokio::fs::{self, File};
 use tokio::io::AsyncWriteExt;
+use trace_ir::PrestateDiff;
+pub use trace_ir::Compression;
 
 mod validation;
+mod tracer;
+mod block;
+mod scanner;
+mod retry;
+mod error;
+mod store;
+mod compression;
+mod metadata;
+mod transaction;
+pub mod adapter;
+
+pub use tracer::{StructLoggerOptions, Tracer, TracerOptions};
+pub use adapter::{ClientAdapter, ClientFlavor};
+pub use block::BlockId;
+pub use validation::validate_trace_file;
+pub use retry::RetryPolicy;
+pub use error::RpcError;
+pub use store::{TraceStore, ArtifactHash, check_tx_hash, normalize_tx_hash, Artifacts, EntryStatus, VerifyItem};
+pub use metadata::{TraceMetadata, FetchRecord, METADATA_VERSION, redact_endpoint};
+pub use transaction::{Transaction, Receipt, ReceiptLog};
+use error::RpcErrorObject;
+use block::BlockTransactions;
+use retry::Failure;
 
 // one fully acquired tx trace
 pub struct RawTrace {
@@ -13,33 +42,66 @@ pub struct RawTrace {
     pub trace_path: PathBuf,  // saved raw exec trace from debug_traceTransaction
     pub receipt_path: PathBuf,  // from eth_getTransactionReceipt
     pub metadata_path: PathBuf,
+    pub client: ClientFlavor,   // how trace-ir has to read trace_path
+}
+
+// outcome for one tx of a batch, a failed tx does not abort the others
+pub struct BatchItem {
+    pub tx_hash: String,
+    pub result: Result<RawTrace>,
 }
 
 // configuration for acquiring traces
 pub struct TraceConfig {
     pub rpc_url: String,
     pub out_dir: PathBuf,
+    pub tracer: TracerOptions,
+    pub client: Option<ClientFlavor>,   // None asks the node via web3_clientVersion
+    pub concurrency: usize,             // max in-flight tx downloads for batch fetches
+    pub retry: RetryPolicy,
+    pub use_cache: bool,                // reuse artifacts already in out_dir when their hashes check out
+    pub compression: Compression,       // how artifacts are written to disk, reads detect it on their own
+    pub headers: BTreeMap<String, String>,  // sent with every request, e.g. Authorization for hosted nodes
+    pub chain_id: Option<u64>,          // when set, refuse to fetch from a node on another chain
+}
+
+impl TraceConfig {
+    pub fn new(rpc_url: String, out_dir: PathBuf) -> Self {
+        Self {
+            rpc_url,
+            out_dir,
+            tracer: TracerOptions::default(),
+            client: None,
+            concurrency: 4,
+            retry: RetryPolicy::default(),
+            use_cache: true,
+            compression: Compression::None,
+            headers: BTreeMap::new(),
+            chain_id: None,
+        }
+    }
 }
 
 // will return string to make rpc req
-pub fn debug_trace_payload(tx_hash: &str) -> String {
-    // raw string
-    format!(
-        r#"{{
-            "jsonrpc": "2.0",
-            "id": 1,
-            "method": "debug_traceTransaction",
-            "params": [
-                "{}",
-                {{
-                    "disableStack": false,
-                    "disableMemory": false,
-                    "disableStorage": false
-                }}
-            ]
-        }}"#,
-        tx_hash
-    )
+pub fn debug_trace_payload(tx_hash: &str, tracer: &TracerOptions) -> String {
+    let request = RpcRequest {
+        jsonrpc: "2.0",
+        id: 1,
+        method: "debug_traceTransaction",
+        params: (tx_hash, tracer.params()),
+    };
+
+    // only fails on non-string map keys, which the request types never have
+    serde_json::to_string(&request).expect("trace request is always serializable")
+}
+
+// field order is fixed so the same request always serializes to the same bytes
+#[derive(Serialize)]
+pub(crate) struct RpcRequest<'a, P> {
+    pub jsonrpc: &'a str,
+    pub id: u64,
+    pub method: &'a str,
+    pub params: P,
 }
 
 pub fn receipt_payload(tx_hash: &str) -> String{
@@ -57,99 +119,470 @@ pub fn receipt_payload(tx_hash: &str) -> String{
     )
 }
 
+// just enough of a JSON-RPC response to read a small scalar result
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    #[serde(default)]
+    error: Option<RpcErrorObject>,
+}
+
 pub struct TraceFetcher {
     client: Client,
     config: TraceConfig,
+    adapter: Box<dyn ClientAdapter>,
+    client_version: Option<String>,
+    chain_id: tokio::sync::OnceCell<u64>,
+    store: TraceStore,
 }
 
 impl TraceFetcher {
+    // uses the configured client, or geth's dialect when none is set
     pub fn new(config: TraceConfig) -> Self {
         let client = Client::builder()
                     .danger_accept_invalid_certs(true)
                     .build()
                     .unwrap();
+        let adapter = adapter::adapter_for(config.client.unwrap_or_default());
+        let store = TraceStore::new(config.out_dir.clone())
+            .expect_config(config.tracer.clone(), config.compression)
+            .expect_chain(config.chain_id);
         Self{
             client, 
-            config
+            config,
+            adapter,
+            client_version: None,
+            chain_id: tokio::sync::OnceCell::new(),
+            store,
+        }
+    }
+
+    // like new, but asks the node which client it runs when the config does not say
+    pub async fn detect(config: TraceConfig) -> Result<Self> {
+        let mut fetcher = Self::new(config);
+
+        let version = fetcher.fetch_client_version().await.context("Failed to detect client")?;
+        if fetcher.config.client.is_none() {
+            fetcher.adapter = adapter::detect_adapter(&version);
+        }
+        fetcher.client_version = Some(version);
+
+        Ok(fetcher)
+    }
+
+    pub fn client(&self) -> ClientFlavor {
+        self.adapter.flavor()
+    }
+
+    pub fn client_version(&self) -> Option<&str> {
+        self.client_version.as_deref()
+    }
+
+    pub fn store(&self) -> &TraceStore {
+        &self.store
+    }
+
+    async fn fetch_client_version(&self) -> Result<String> {
+        self.rpc_call(&adapter::client_version_payload()).await
+            .context("Invalid web3_clientVersion response")
+    }
+
+    // asked once per fetcher, a node does not change chains under us
+    pub async fn chain_id(&self) -> Result<u64> {
+        self.chain_id.get_or_try_init(|| async {
+            let chain_id: String = self.rpc_call(&adapter::chain_id_payload()).await
+                .context("Invalid eth_chainId response")?;
+            let chain_id = metadata::parse_quantity(&chain_id)?;
+
+            if let Some(expected) = self.config.chain_id
+                && expected != chain_id
+            {
+                bail!("node is on chain {} but chain {} was expected", chain_id, expected);
+            }
+            Ok(chain_id)
+        }).await.copied()
+    }
+
+    // a JSON-RPC POST carrying the configured headers
+    fn post(&self, payload: &str) -> RequestBuilder {
+        let mut request = self.client
+            .post(&self.config.rpc_url)
+            .header("Content-Type", "application/json");
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+        request.body(payload.to_string())
+    }
+
+    // for small responses only, anything trace sized goes through stream_rpc_response
+    async fn rpc_call<T: DeserializeOwned>(&self, payload: &str) -> Result<T> {
+        let body = self.with_retry("Rpc call", || async {
+            let res = self.post(payload).send().await?;
+
+            if !res.status().is_success() {
+                return Err(Failure::from_status(res.status(), res.headers()));
+            }
+            Ok(res.bytes().await?)
+        }).await?;
+
+        let response: RpcResponse<T> = serde_json::from_slice(&body)?;
+        if let Some(err) = response.error {
+            return Err(anyhow::Error::new(RpcError::from(err)));
+        }
+        response.result.context("Rpc response has no result")
+    }
+
+    // the tx itself, the trace does not say who sent how much with which input
+    pub async fn transaction(&self, tx_hash: &str) -> Result<Transaction> {
+        self.rpc_call(&self.adapter.transaction_payload(tx_hash)).await
+            .context("Invalid eth_getTransactionByHash response")
+    }
+
+    // prestateTracer in diffMode, to cross-check a StateDiff built from the trace.
+    // only touched accounts come back, so unlike structLogs this fits in memory
+    pub async fn prestate_diff(&self, tx_hash: &str) -> Result<PrestateDiff> {
+        let tracer = TracerOptions {
+            tracer: Tracer::PrestateTracer { diff_mode: true },
+            timeout: self.config.tracer.timeout.clone(),
+        };
+        self.rpc_call(&self.adapter.trace_transaction_payload(tx_hash, &tracer)).await
+            .context("Invalid prestateTracer response")
+    }
+
+    fn tx_dir(&self, tx_hash: &str) -> Result<PathBuf> {
+        self.store.tx_dir(tx_hash)
+    }
+
+    // a valid cached copy fetched with the same config, corrupt entries are reported and refetched.
+    // rehashing the artifacts is blocking file io
+    async fn cached(&self, tx_hash: &str) -> Option<RawTrace> {
+        if !self.config.use_cache {
+            return None;
+        }
+        let store = self.store.clone();
+        let owned_hash = tx_hash.to_string();
+        let cached = tokio::task::spawn_blocking(move || store.get(&owned_hash))
+            .await
+            .context("cache lookup panicked")
+            .and_then(|result| result);
+        match cached {
+            Ok(Some(trace)) => {
+                eprintln!("[{}] Using cached trace", tx_hash);
+                Some(trace)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                eprintln!("[{}] Cached trace unusable ({:#}), fetching again", tx_hash, e);
+                None
+            }
         }
     }
 
     pub async fn fetch_transaction(&self, tx_hash: &str) -> Result<RawTrace> {
-        
-        let base_path = self.config.out_dir.join(tx_hash);
+        let tx_hash = &normalize_tx_hash(tx_hash)?;
+        let base_path = self.tx_dir(tx_hash)?;
+        if let Some(trace) = self.cached(tx_hash).await {
+            return Ok(trace);
+        }
+        // a profile pinned to another chain must fail before anything is downloaded,
+        // cached entries from another chain were already skipped by the store
+        self.chain_id().await?;
 
         if !base_path.exists() {
             fs::create_dir_all(&base_path).await.context("Failed to create tx directory")?;
         }
 
-        let trace_path = base_path.join("trace.json");
-        let receipt_path = base_path.join("receipt.json");
-        let metadata_path = base_path.join("metadata.json");
+        let trace_path = base_path.join(store::TRACE_FILE);
+        // stale metadata must not vouch for files that are about to be replaced
+        remove_partial(&[&base_path.join(store::METADATA_FILE)]).await;
 
-        // println!("[{}] Requesting Debug trace ...", tx_hash);
-        // let trace_rpc_payload = debug_trace_payload(tx_hash);
-        // self.stream_rpc_response(&trace_rpc_payload, &trace_path).await
-        // .context("Failed to download trace")?;
+        eprintln!("[{}] Requesting Debug trace ...", tx_hash);
+        let trace_rpc_payload = self.adapter.trace_transaction_payload(tx_hash, &self.config.tracer);
+        if let Err(e) = self.stream_rpc_response(&trace_rpc_payload, &trace_path).await {
+            remove_partial(&[&trace_path]).await;
+            return Err(e.context("Failed to download trace"));
+        }
 
-        println!("[{}] Requesting receipt ...", tx_hash);
-        let receipt_rpc_payload = receipt_payload(tx_hash);
-        self.stream_rpc_response(&receipt_rpc_payload, &receipt_path).await.
-        context("Failed to download receipt")?;
+        self.complete_transaction(tx_hash).await
+    }
 
-        let metadata = json!({
-            "tx_hash": tx_hash,
-            "fetched_at": chrono::Utc::now().to_rfc3339(),
-            "rpc_url" : self.config.rpc_url,
-            "version": "1.0"
-        });
+    // everything after trace.json is on disk: receipt, validation, metadata
+    async fn complete_transaction(&self, tx_hash: &str) -> Result<RawTrace> {
+        let base_path = self.tx_dir(tx_hash)?;
+        let trace_path = base_path.join(store::TRACE_FILE);
+        let receipt_path = base_path.join(store::RECEIPT_FILE);
+        let metadata_path = base_path.join(store::METADATA_FILE);
 
-        fs::write(&metadata_path, serde_json::to_string_pretty(&metadata)?).await?;
+        eprintln!("[{}] Requesting receipt ...", tx_hash);
+        let receipt_rpc_payload = self.adapter.receipt_payload(tx_hash);
+        if let Err(e) = self.stream_rpc_response(&receipt_rpc_payload, &receipt_path).await {
+            remove_partial(&[&trace_path, &receipt_path]).await;
+            return Err(e.context("Failed to download receipt"));
+        }
+
+        eprintln!(" Validating trace integrity for [{}] ", tx_hash);
+        let validated = validate_artifacts(&trace_path, &receipt_path).await;
+
+        if let Err(e) = validated {
+            // never leave a half-valid artifact set behind for the next stage to pick up
+            remove_partial(&[&trace_path, &receipt_path]).await;
+            return Err(e);
+        }
+        eprintln!("Trace is Valid!!");
 
-        println!(" Validating trace integrity for [{}] ", tx_hash);
-        match validation::validate_trace_file(&receipt_path) {
-            Ok(_) => println!("Trace is Valid!!"),
+        let metadata = match self.build_metadata(tx_hash, &trace_path, &receipt_path).await {
+            Ok(metadata) => metadata,
             Err(e) => {
-                //let _ = tokio::fs::remove_file(&trace_path).await;
-                return Err(e.context("Trace validation failed"))
-                
+                remove_partial(&[&trace_path, &receipt_path]).await;
+                return Err(e.context("Failed to build metadata"));
             }
-        }
+        };
+
+        let fetch_record = FetchRecord::now();
+        fs::write(base_path.join(store::FETCH_FILE), serde_json::to_string_pretty(&fetch_record)?).await?;
+
+        // metadata is written last so its presence marks a complete download
+        fs::write(&metadata_path, serde_json::to_string_pretty(&metadata)?).await?;
 
         Ok(RawTrace{
             tx_hash: tx_hash.to_string(),
             trace_path,
             receipt_path,
             metadata_path,
+            client: self.client(),
+        })
+
+
+    }
+
+    async fn build_metadata(&self, tx_hash: &str, trace_path: &Path, receipt_path: &Path) -> Result<TraceMetadata> {
+        let block = metadata::receipt_block(receipt_path)?;
+
+        Ok(TraceMetadata {
+            schema_version: METADATA_VERSION,
+            tx_hash: tx_hash.to_string(),
+            chain_id: self.chain_id().await?,
+            block_number: block.number,
+            block_hash: block.hash,
+            client: self.client(),
+            client_version: self.client_version.clone(),
+            endpoint: redact_endpoint(&self.config.rpc_url),
+            tracer: self.config.tracer.clone(),
+            compression: self.config.compression,
+            artifacts: hash_artifacts(trace_path, receipt_path).await?,
         })
+    }
 
+    // fetches every hash with at most `concurrency` downloads in flight, results keep input order.
+    // a hash listed twice is fetched and reported once, two downloads would race for its directory
+    pub async fn fetch_many(&self, tx_hashes: &[String]) -> Vec<BatchItem> {
+        // anything that is not a tx hash is passed on as is, fetch_transaction reports it
+        let mut seen = BTreeSet::new();
+        let unique: Vec<String> = tx_hashes.iter()
+            .map(|tx_hash| normalize_tx_hash(tx_hash).unwrap_or_else(|_| tx_hash.clone()))
+            .filter(|tx_hash| seen.insert(tx_hash.clone()))
+            .collect();
 
+        stream::iter(unique)
+            .map(|tx_hash| async move {
+                BatchItem {
+                    result: self.fetch_transaction(&tx_hash).await,
+                    tx_hash,
+                }
+            })
+            .buffered(self.config.concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    // traces every tx of a block with one debug_traceBlockBy* call and splits the result per tx.
+    // falls back to per-tx requests when the node cannot trace the block (or a single tx in it)
+    pub async fn fetch_block(&self, block: &BlockId) -> Result<Vec<BatchItem>> {
+        self.chain_id().await?;
+        let block_info: BlockTransactions = self.rpc_call(&self.adapter.block_payload(block)).await
+            .with_context(|| format!("Failed to fetch block {}", block))?;
+        let tx_hashes = block_info.transactions.iter()
+            .map(|tx_hash| normalize_tx_hash(tx_hash))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("block {} lists an invalid tx hash", block))?;
+
+        // nothing to trace when every tx of the block is already cached
+        let cached: Vec<Option<RawTrace>> = stream::iter(&tx_hashes)
+            .map(|tx_hash| self.cached(tx_hash))
+            .buffered(self.config.concurrency.max(1))
+            .collect()
+            .await;
+        if cached.iter().all(Option::is_some) {
+            return Ok(tx_hashes.into_iter()
+                .zip(cached)
+                .map(|(tx_hash, trace)| BatchItem { tx_hash, result: Ok(trace.expect("checked above")) })
+                .collect());
+        }
+        // cached entries stay as they are, only the missing txs are split out and completed
+        let keep: BTreeSet<String> = cached.iter().flatten().map(|trace| trace.tx_hash.clone()).collect();
+
+        fs::create_dir_all(&self.config.out_dir).await.context("Failed to create output directory")?;
+        let block_path = self.config.out_dir.join(format!(".block-{}.json", block));
+
+        eprintln!("[block {}] Requesting block trace for {} txs ...", block, tx_hashes.len());
+        let payload = self.adapter.trace_block_payload(block, &self.config.tracer);
+        let split = match self.stream_rpc_response(&payload, &block_path).await {
+            Ok(()) => self.split_block_trace(&block_path, &tx_hashes, keep).await,
+            Err(e) => Err(e),
+        };
+        remove_partial(&[&block_path]).await;
+
+        // None for every tx when the block could not be traced as a whole
+        let traces: Vec<Option<Result<PathBuf>>> = match split {
+            Ok(traces) => traces.into_iter().map(Some).collect(),
+            Err(e) => {
+                eprintln!("[block {}] Block trace unavailable ({:#}), tracing txs one by one", block, e);
+                tx_hashes.iter().map(|_| None).collect()
+            }
+        };
+
+        let items = stream::iter(tx_hashes.iter().zip(cached).zip(traces))
+            .map(|((tx_hash, cached), trace)| async move {
+                let result = match (cached, trace) {
+                    (Some(cached), _) => Ok(cached),
+                    (None, Some(Ok(_))) => self.complete_transaction(tx_hash).await,
+                    (None, Some(Err(e))) => {
+                        eprintln!("[{}] {:#}, retrying on its own", tx_hash, e);
+                        self.fetch_transaction(tx_hash).await
+                    }
+                    (None, None) => self.fetch_transaction(tx_hash).await,
+                };
+                BatchItem { tx_hash: tx_hash.clone(), result }
+            })
+            .buffered(self.config.concurrency.max(1))
+            .collect()
+            .await;
+
+        Ok(items)
+    }
+
+    // reading, decompressing and recompressing the block trace is blocking file io
+    async fn split_block_trace(&self, block_path: &Path, tx_hashes: &[String], keep: BTreeSet<String>) -> Result<Vec<Result<PathBuf>>> {
+        let block_path = block_path.to_path_buf();
+        let tx_hashes = tx_hashes.to_vec();
+        let out_dir = self.config.out_dir.clone();
+        let compression = self.config.compression;
+        tokio::task::spawn_blocking(move || block::split_block_trace(&block_path, &tx_hashes, &keep, &out_dir, compression))
+            .await
+            .context("block trace split panicked")?
+    }
+
+    // runs `attempt` until it succeeds, fails fatally or the retry policy gives up
+    async fn with_retry<T, F, Fut>(&self, what: &str, mut attempt: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, Failure>>,
+    {
+        let policy = &self.config.retry;
+        let mut tries = 0;
+
+        loop {
+            tries += 1;
+            match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(Failure::Transient { error, retry_after }) if tries < policy.max_attempts => {
+                    let wait = policy.wait(tries, retry_after);
+                    eprintln!("{} failed ({:#}), retrying in {:?} [{}/{}]", what, error, wait, tries, policy.max_attempts);
+                    tokio::time::sleep(wait).await;
+                }
+                Err(Failure::Transient { error, .. }) | Err(Failure::Fatal(error)) => {
+                    return Err(error.context(format!("{} failed after {} attempt(s)", what, tries)));
+                }
+            }
+        }
     }
 
     // Path is borrowed and cannot be modified
+    // the body goes to a temp file which only replaces out_path once it is complete,
+    // every retry restarts the download from scratch
     async fn stream_rpc_response(&self, payload: &str, out_path: &Path ) -> Result<()>{
-        let mut res = self.client
-        .post(&self.config.rpc_url)
-        .header("Content-Type", "application/json")
-        .body(payload.to_string())
-        .send()
-        .await?;
+        let part_path = partial_path(out_path);
 
-        
+        let downloaded = self.with_retry("Rpc download", || async {
+            let result = self.download_once(payload, &part_path).await;
+            if result.is_err() {
+                let _ = fs::remove_file(&part_path).await;
+            }
+            result
+        }).await;
 
-        res.error_for_status_ref()?;
-        println!("receipt: {:?}", res);
+        match downloaded {
+            Ok(()) => {
+                fs::rename(&part_path, out_path).await.context("Failed to move download into place")?;
+                Ok(())
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&part_path).await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn download_once(&self, payload: &str, out_path: &Path) -> std::result::Result<(), Failure> {
+        let mut res = self.post(payload).send().await?;
+
+        if !res.status().is_success() {
+            return Err(Failure::from_status(res.status(), res.headers()));
+        }
 
-        let mut file = File::create(out_path).await?;
+        let file = File::create(out_path).await?;
+        let mut out = compression::async_writer(file, self.config.compression);
 
         while let Some(chunk) = res.chunk().await? {
-            file.write_all(&chunk).await?;
+            out.write_all(&chunk).await?;
         }
 
-        file.flush().await?;
+        // finishes the compressed stream as well as flushing
+        out.shutdown().await?;
         Ok(())
     }
 
 
 }
 
+// sibling temp file used while a download is in progress
+pub(crate) fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+// validation decompresses and parses the whole trace, blocking file io like hashing
+async fn validate_artifacts(trace_path: &Path, receipt_path: &Path) -> Result<()> {
+    let trace_path = trace_path.to_path_buf();
+    let receipt_path = receipt_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        validation::validate_trace_file(&trace_path)
+            .context("Trace validation failed")
+            .and_then(|_| validation::validate_trace_file(&receipt_path).context("Receipt validation failed"))
+    })
+    .await
+    .context("artifact validation panicked")?
+}
+
+// hashing traces far bigger than memory is blocking file io
+async fn hash_artifacts(trace_path: &Path, receipt_path: &Path) -> Result<Artifacts> {
+    let trace_path = trace_path.to_path_buf();
+    let receipt_path = receipt_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        Ok(Artifacts {
+            trace: ArtifactHash::of_file(&trace_path).context("Failed to hash trace")?,
+            receipt: ArtifactHash::of_file(&receipt_path).context("Failed to hash receipt")?,
+        })
+    })
+    .await
+    .context("artifact hashing panicked")?
+}
+
+// best effort removal of artifacts from a failed fetch
+async fn remove_partial(paths: &[&Path]) {
+    for path in paths {
+        let _ = fs::remove_file(path).await;
+    }
+}
 
@@ -0,0 +1,159 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::{Serialize, Deserialize};
+
+// how hard to try before giving up on a request
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,          // total tries, 1 means no retries
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub jitter: bool,               // randomize each wait so parallel fetches do not retry in lockstep
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self { max_attempts: 1, ..Self::default() }
+    }
+
+    // wait before retry number `attempt` (1 based): doubles every time, capped,
+    // with jitter picked uniformly from [cap/2, cap]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self.initial_backoff_ms.saturating_mul(1u64 << attempt.saturating_sub(1).min(32));
+        let capped = exp.min(self.max_backoff_ms);
+
+        if !self.jitter || capped == 0 {
+            return Duration::from_millis(capped);
+        }
+        let half = capped / 2;
+        Duration::from_millis(half + random_u64() % (capped - half + 1))
+    }
+
+    // the server's Retry-After wins over our backoff, but never beyond max_backoff_ms
+    pub(crate) fn wait(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(wait) => wait.min(Duration::from_millis(self.max_backoff_ms)),
+            None => self.backoff(attempt),
+        }
+    }
+}
+
+// std only randomness, every RandomState is seeded with fresh keys
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u8(0);
+    hasher.finish()
+}
+
+// why one attempt failed
+pub(crate) enum Failure {
+    // worth another try, optionally after the delay the server asked for
+    Transient { error: anyhow::Error, retry_after: Option<Duration> },
+    Fatal(anyhow::Error),
+}
+
+impl Failure {
+    pub(crate) fn from_status(status: StatusCode, headers: &HeaderMap) -> Self {
+        let error = anyhow::anyhow!("Rpc endpoint returned HTTP {}", status);
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            Failure::Transient { error, retry_after: retry_after(headers) }
+        } else {
+            Failure::Fatal(error)
+        }
+    }
+}
+
+// dropped connections, timeouts and truncated bodies are all worth retrying
+impl From<reqwest::Error> for Failure {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() || e.is_timeout() || e.is_request() || e.is_body() || e.is_decode() {
+            Failure::Transient { error: e.into(), retry_after: None }
+        } else {
+            Failure::Fatal(e.into())
+        }
+    }
+}
+
+// local disk problems will not go away by asking the node again
+impl From<std::io::Error> for Failure {
+    fn from(e: std::io::Error) -> Self {
+        Failure::Fatal(e.into())
+    }
+}
+
+// Retry-After is either delay-seconds or an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = at.signed_duration_since(chrono::Utc::now()).to_std().unwrap_or(Duration::ZERO);
+    Some(wait)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_backoff_doubles_and_caps() {
+        let policy = RetryPolicy { jitter: false, ..RetryPolicy::default() };
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_millis(1000));
+        assert_eq!(policy.backoff(3), Duration::from_millis(2000));
+        assert_eq!(policy.backoff(20), Duration::from_millis(30_000));
+    }
+
+    #[test]
+    fn test_jitter_stays_in_range() {
+        let policy = RetryPolicy::default();
+        for _ in 0..100 {
+            let wait = policy.backoff(3);
+            assert!(wait >= Duration::from_millis(1000) && wait <= Duration::from_millis(2000));
+        }
+    }
+
+    #[test]
+    fn test_retry_after_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.wait(1, retry_after(&headers)), Duration::from_secs(7));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("86400"));
+        assert_eq!(policy.wait(1, retry_after(&headers)), Duration::from_millis(policy.max_backoff_ms));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        assert!(matches!(
+            Failure::from_status(StatusCode::TOO_MANY_REQUESTS, &headers),
+            Failure::Transient { .. }
+        ));
+        assert!(matches!(
+            Failure::from_status(StatusCode::UNAUTHORIZED, &headers),
+            Failure::Fatal(_)
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+use std::io::{BufRead, Write};
+use anyhow::{Result, Context, anyhow, bail};
+
+// Byte level JSON walker for when values have to be copied verbatim, which serde cannot do
+// without buffering them. It only tracks nesting and strings, the copied bytes are not validated.
+pub(crate) struct JsonScanner<R> {
+    reader: R,
+}
+
+impl<R: BufRead> JsonScanner<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    pub(crate) fn peek(&mut self) -> Result<Option<u8>> {
+        Ok(self.reader.fill_buf()?.first().copied())
+    }
+
+    pub(crate) fn next_byte(&mut self) -> Result<u8> {
+        let b = self.peek()?.ok_or_else(|| anyhow!("unexpected end of JSON input"))?;
+        self.reader.consume(1);
+        Ok(b)
+    }
+
+    pub(crate) fn skip_whitespace(&mut self) -> Result<()> {
+        while let Some(b) = self.peek()? {
+            if !b.is_ascii_whitespace() {
+                break;
+            }
+            self.reader.consume(1);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn expect(&mut self, expected: u8) -> Result<()> {
+        self.skip_whitespace()?;
+        let b = self.next_byte()?;
+        if b != expected {
+            bail!("expected '{}' but found '{}'", expected as char, b as char);
+        }
+        Ok(())
+    }
+
+    // consumes `expected` if it is the next non whitespace byte
+    pub(crate) fn eat(&mut self, expected: u8) -> Result<bool> {
+        self.skip_whitespace()?;
+        if self.peek()? == Some(expected) {
+            self.reader.consume(1);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // copies one JSON value verbatim into `sink` (or drops it).
+    // scans whole buffered chunks so a compressing sink sees one write per chunk, not per byte
+    pub(crate) fn copy_value(&mut self, mut sink: Option<&mut dyn Write>) -> Result<()> {
+        self.skip_whitespace()?;
+
+        let mut nesting = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        loop {
+            let chunk = self.reader.fill_buf()?;
+            if chunk.is_empty() {
+                if nesting == 0 && !in_string {
+                    return Ok(());
+                }
+                bail!("unexpected end of JSON input");
+            }
+
+            // bytes of the chunk that belong to the value, and whether the value ends with them
+            let mut taken = 0;
+            let mut done = false;
+            for &b in chunk {
+                if !in_string && nesting == 0 && (matches!(b, b',' | b'}' | b']') || b.is_ascii_whitespace()) {
+                    done = true;
+                    break;
+                }
+                taken += 1;
+
+                if in_string {
+                    match b {
+                        _ if escaped => escaped = false,
+                        b'\\' => escaped = true,
+                        b'"' => {
+                            in_string = false;
+                            if nesting == 0 {
+                                done = true;
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+
+                match b {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => nesting += 1,
+                    b'}' | b']' => {
+                        nesting = nesting.checked_sub(1).ok_or_else(|| anyhow!("unbalanced '{}'", b as char))?;
+                        if nesting == 0 {
+                            done = true;
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if let Some(out) = sink.as_mut() {
+                out.write_all(&chunk[..taken])?;
+            }
+            self.reader.consume(taken);
+            if done {
+                return Ok(());
+            }
+        }
+    }
+
+    pub(crate) fn skip_value(&mut self) -> Result<()> {
+        self.copy_value(None)
+    }
+
+    // one value, raw, for the small parts (keys, error objects) that are worth handing to serde
+    pub(crate) fn read_raw(&mut self) -> Result<Vec<u8>> {
+        let mut raw = Vec::new();
+        self.copy_value(Some(&mut raw))?;
+        Ok(raw)
+    }
+
+    pub(crate) fn read_string(&mut self) -> Result<String> {
+        let raw = self.read_raw()?;
+        serde_json::from_slice(&raw).context("expected a JSON string")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copies_values_verbatim() {
+        let input = br#" {"a": [1, "]}\"", {"b": null}]} , "key" ,7"#;
+        let mut scanner = JsonScanner::new(&input[..]);
+
+        let mut out = Vec::new();
+        scanner.copy_value(Some(&mut out)).unwrap();
+        assert_eq!(out, br#"{"a": [1, "]}\"", {"b": null}]}"#);
+        assert!(scanner.eat(b',').unwrap());
+        assert_eq!(scanner.read_string().unwrap(), "key");
+        assert!(!scanner.eat(b':').unwrap());
+        scanner.expect(b',').unwrap();
+        assert_eq!(scanner.read_raw().unwrap(), b"7");
+        assert_eq!(scanner.peek().unwrap(), None);
+    }
+
+    // counts the write calls that reach it
+    struct CountingSink(Vec<u8>, usize);
+
+    impl Write for CountingSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.1 += 1;
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_copies_across_chunks() {
+        let value = br#"{"structLogs": [{"op": "STOP", "note": "a \" , ] b"}], "gas": 21000}"#;
+        let input = [&value[..], b", 1"].concat();
+        let mut scanner = JsonScanner::new(std::io::BufReader::with_capacity(8, &input[..]));
+
+        let mut sink = CountingSink(Vec::new(), 0);
+        scanner.copy_value(Some(&mut sink)).unwrap();
+        assert_eq!(sink.0, value);
+        // one write per buffered chunk, not one per byte
+        assert_eq!(sink.1, value.len().div_ceil(8));
+        scanner.expect(b',').unwrap();
+        assert_eq!(scanner.read_raw().unwrap(), b"1");
+    }
+
+    #[test]
+    fn test_truncated_value_errors() {
+        let mut scanner = JsonScanner::new(&br#"{"a": "b"#[..]);
+        assert!(scanner.skip_value().is_err());
+    }
+}
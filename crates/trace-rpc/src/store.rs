@@ -0,0 +1,331 @@
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use anyhow::{Result, Context, bail};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use trace_ir::Compression;
+
+use crate::{RawTrace, TracerOptions};
+use crate::metadata::{TraceMetadata, METADATA_VERSION};
+
+pub(crate) const TRACE_FILE: &str = "trace.json";
+pub(crate) const RECEIPT_FILE: &str = "receipt.json";
+pub(crate) const METADATA_FILE: &str = "metadata.json";
+pub(crate) const FETCH_FILE: &str = "fetch.json";
+
+// tx hashes name directories under the store root, so anything but `0x` and 64 hex digits
+// (an absolute path, `..`) could reach outside of it
+pub fn check_tx_hash(tx_hash: &str) -> Result<()> {
+    let valid = tx_hash
+        .strip_prefix("0x")
+        .is_some_and(|digits| digits.len() == 64 && digits.bytes().all(|b| b.is_ascii_hexdigit()));
+    if !valid {
+        bail!("{:?} is not a transaction hash", tx_hash);
+    }
+    Ok(())
+}
+
+// the one spelling of a tx hash used for directories and metadata, so `0xAB..` and `0xab..`
+// share an entry instead of fetching the same tx twice
+pub fn normalize_tx_hash(tx_hash: &str) -> Result<String> {
+    check_tx_hash(tx_hash)?;
+    Ok(tx_hash.to_ascii_lowercase())
+}
+
+// content hash of one artifact as recorded in metadata.json
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactHash {
+    pub sha256: String,
+    pub size: u64,
+}
+
+impl ArtifactHash {
+    // hashes in chunks, traces can be far bigger than memory
+    pub fn of_file(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+        let mut reader = BufReader::new(file);
+        let mut hasher = Sha256::new();
+        let mut buf = [0u8; 64 * 1024];
+        let mut size = 0u64;
+
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            size += n as u64;
+        }
+
+        Ok(Self { sha256: format!("{:x}", hasher.finalize()), size })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Artifacts {
+    pub trace: ArtifactHash,
+    pub receipt: ArtifactHash,
+}
+
+// state of one cached tx after checking its hashes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntryStatus {
+    Valid,
+    // no metadata.json, the download never finished
+    Incomplete,
+    // an artifact is missing or its bytes no longer match the recorded hash
+    Corrupt { artifact: String },
+}
+
+pub struct VerifyItem {
+    pub tx_hash: String,
+    pub status: EntryStatus,
+}
+
+// Trace artifacts on disk, one directory per tx: out_dir/<tx_hash>/{trace,receipt,metadata}.json.
+// metadata.json is written last and records the sha256 of the other two,
+// so an entry is only trusted when every recorded hash still matches.
+#[derive(Clone)]
+pub struct TraceStore {
+    root: PathBuf,
+    expected: Option<(TracerOptions, Compression)>,
+    expected_chain: Option<u64>,
+}
+
+impl TraceStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into(), expected: None, expected_chain: None }
+    }
+
+    // entries fetched with another tracer or compression are misses for get
+    pub fn expect_config(mut self, tracer: TracerOptions, compression: Compression) -> Self {
+        self.expected = Some((tracer, compression));
+        self
+    }
+
+    // entries fetched from another chain are misses for get
+    pub fn expect_chain(mut self, chain_id: Option<u64>) -> Self {
+        self.expected_chain = chain_id;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // errors for anything that is not a tx hash, the name must not lead out of the root
+    pub fn tx_dir(&self, tx_hash: &str) -> Result<PathBuf> {
+        Ok(self.root.join(normalize_tx_hash(tx_hash)?))
+    }
+
+    // a cached trace whose hashes check out, None when absent, incomplete or fetched with another config or chain.
+    // corruption is an error so callers can tell it apart from a plain miss
+    pub fn get(&self, tx_hash: &str) -> Result<Option<RawTrace>> {
+        let Some(metadata) = self.metadata(tx_hash)? else {
+            return Ok(None);
+        };
+        if let Some((tracer, compression)) = &self.expected
+            && (&metadata.tracer != tracer || metadata.compression != *compression)
+        {
+            return Ok(None);
+        }
+        if self.expected_chain.is_some_and(|chain_id| chain_id != metadata.chain_id) {
+            return Ok(None);
+        }
+        if let Some(artifact) = self.check_artifacts(tx_hash, &metadata.artifacts)? {
+            bail!("cached {} for {} does not match its recorded hash", artifact, tx_hash);
+        }
+
+        let dir = self.tx_dir(tx_hash)?;
+        Ok(Some(RawTrace {
+            tx_hash: metadata.tx_hash,
+            trace_path: dir.join(TRACE_FILE),
+            receipt_path: dir.join(RECEIPT_FILE),
+            metadata_path: dir.join(METADATA_FILE),
+            client: metadata.client,
+        }))
+    }
+
+    // tx hashes with a complete entry (metadata.json present), sorted
+    pub fn list(&self) -> Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("could not read trace store"),
+        };
+
+        let mut hashes = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // dot files are scratch space (block downloads etc.), anything else is not ours
+            if normalize_tx_hash(&name).ok().as_ref() != Some(&name) || !entry.file_type()?.is_dir() {
+                continue;
+            }
+            if entry.path().join(METADATA_FILE).is_file() {
+                hashes.push(name);
+            }
+        }
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    // removes everything stored for a tx, false when there was nothing to remove
+    pub fn evict(&self, tx_hash: &str) -> Result<bool> {
+        match fs::remove_dir_all(self.tx_dir(tx_hash)?) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("could not evict {}", tx_hash)),
+        }
+    }
+
+    pub fn verify(&self, tx_hash: &str) -> Result<EntryStatus> {
+        let Some(metadata) = self.metadata(tx_hash)? else {
+            return Ok(EntryStatus::Incomplete);
+        };
+        Ok(match self.check_artifacts(tx_hash, &metadata.artifacts)? {
+            Some(artifact) => EntryStatus::Corrupt { artifact: artifact.to_string() },
+            None => EntryStatus::Valid,
+        })
+    }
+
+    // rehashes every listed entry
+    pub fn verify_all(&self) -> Result<Vec<VerifyItem>> {
+        self.list()?
+            .into_iter()
+            .map(|tx_hash| {
+                let status = self.verify(&tx_hash)?;
+                Ok(VerifyItem { tx_hash, status })
+            })
+            .collect()
+    }
+
+    // metadata of a complete entry, without checking the artifacts against it
+    pub fn metadata(&self, tx_hash: &str) -> Result<Option<TraceMetadata>> {
+        let path = self.tx_dir(tx_hash)?.join(METADATA_FILE);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("could not open metadata"),
+        };
+        let metadata: TraceMetadata = serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("invalid metadata for {}", tx_hash))?;
+        if metadata.schema_version != METADATA_VERSION {
+            bail!("metadata for {} has schema version {}, expected {}", tx_hash, metadata.schema_version, METADATA_VERSION);
+        }
+        Ok(Some(metadata))
+    }
+
+    // name of the first artifact that fails its hash check
+    fn check_artifacts(&self, tx_hash: &str, artifacts: &Artifacts) -> Result<Option<&'static str>> {
+        let dir = self.tx_dir(tx_hash)?;
+        for (name, expected) in [(TRACE_FILE, &artifacts.trace), (RECEIPT_FILE, &artifacts.receipt)] {
+            let path = dir.join(name);
+            if !path.is_file() {
+                return Ok(Some(name));
+            }
+            if &ArtifactHash::of_file(&path)? != expected {
+                return Ok(Some(name));
+            }
+        }
+        Ok(None)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientFlavor, StructLoggerOptions};
+
+    fn write_entry(store: &TraceStore, tx_hash: &str) {
+        let dir = store.tx_dir(tx_hash).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(TRACE_FILE), r#"{"jsonrpc":"2.0","id":1,"result":{}}"#).unwrap();
+        fs::write(dir.join(RECEIPT_FILE), r#"{"jsonrpc":"2.0","id":1,"result":{}}"#).unwrap();
+
+        let metadata = TraceMetadata {
+            schema_version: METADATA_VERSION,
+            tx_hash: tx_hash.to_string(),
+            chain_id: 1,
+            block_number: 1,
+            block_hash: "0x01".to_string(),
+            client: ClientFlavor::Geth,
+            client_version: None,
+            endpoint: "http://localhost:8545".to_string(),
+            tracer: TracerOptions::default(),
+            compression: Compression::None,
+            artifacts: Artifacts {
+                trace: ArtifactHash::of_file(&dir.join(TRACE_FILE)).unwrap(),
+                receipt: ArtifactHash::of_file(&dir.join(RECEIPT_FILE)).unwrap(),
+            },
+        };
+        fs::write(dir.join(METADATA_FILE), serde_json::to_string(&metadata).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_store_roundtrip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let store = TraceStore::new(root);
+        let [aa, bb, cc] = ["aa", "bb", "cc"].map(|b| format!("0x{}", b.repeat(32)));
+
+        write_entry(&store, &bb);
+        write_entry(&store, &aa);
+        fs::create_dir_all(store.tx_dir(&cc).unwrap()).unwrap();
+        fs::write(root.join(".block-1.json"), "{}").unwrap();
+
+        assert_eq!(store.list().unwrap(), vec![aa.clone(), bb.clone()]);
+        assert_eq!(store.get(&aa).unwrap().unwrap().client, ClientFlavor::Geth);
+        assert!(store.get(&cc).unwrap().is_none());
+
+        // the hash is the same tx whatever its case
+        let upper = format!("0x{}", "AA".repeat(32));
+        assert_eq!(store.tx_dir(&upper).unwrap(), store.tx_dir(&aa).unwrap());
+        assert_eq!(store.get(&upper).unwrap().unwrap().tx_hash, aa);
+
+        // same entry, but the fetcher now traces without memory
+        let tracer = TracerOptions::struct_logger(StructLoggerOptions { enable_memory: false, ..StructLoggerOptions::default() });
+        assert!(store.clone().expect_config(TracerOptions::default(), Compression::None).get(&aa).unwrap().is_some());
+        assert!(store.clone().expect_config(tracer, Compression::None).get(&aa).unwrap().is_none());
+        assert!(store.clone().expect_config(TracerOptions::default(), Compression::Zstd).get(&aa).unwrap().is_none());
+        assert!(store.clone().expect_chain(Some(1)).get(&aa).unwrap().is_some());
+        assert!(store.clone().expect_chain(Some(10)).get(&aa).unwrap().is_none());
+        assert_eq!(store.verify(&cc).unwrap(), EntryStatus::Incomplete);
+
+        // same size, different bytes
+        fs::write(store.tx_dir(&bb).unwrap().join(TRACE_FILE), r#"{"jsonrpc":"2.0","id":2,"result":{}}"#).unwrap();
+        assert!(store.get(&bb).is_err());
+
+        let report = store.verify_all().unwrap();
+        assert_eq!(report[0].status, EntryStatus::Valid);
+        assert_eq!(report[1].status, EntryStatus::Corrupt { artifact: TRACE_FILE.to_string() });
+
+        assert!(store.evict(&bb).unwrap());
+        assert!(!store.evict(&bb).unwrap());
+        assert_eq!(store.list().unwrap(), vec![aa.clone()]);
+
+        // names from the user never lead out of the root
+        fs::create_dir_all(root.join("not-a-hash")).unwrap();
+        fs::write(root.join("not-a-hash").join(METADATA_FILE), "{}").unwrap();
+        assert_eq!(store.list().unwrap(), vec![aa.clone()]);
+        for name in ["../..", "/tmp", "not-a-hash", "0xaa"] {
+            assert!(store.evict(name).is_err());
+            assert!(store.get(name).is_err());
+            assert!(store.metadata(name).is_err());
+        }
+        assert!(root.join("not-a-hash").exists());
+    }
+
+    #[test]
+    fn test_hashes_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("artifact");
+        fs::write(&path, "abc").unwrap();
+        let hash = ArtifactHash::of_file(&path).unwrap();
+        assert_eq!(hash.sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hash.size, 3);
+    }
+}
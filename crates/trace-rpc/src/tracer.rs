@@ -0,0 +1,214 @@
+use serde::{Serialize, Deserialize};
+
+// options for the default struct logger (opcode level structLogs)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StructLoggerOptions {
+    pub enable_memory: bool,
+    pub disable_stack: bool,
+    pub disable_storage: bool,
+    pub enable_return_data: bool,
+    pub limit: Option<u64>,           // max number of steps, 0/None means unlimited
+}
+
+impl Default for StructLoggerOptions {
+    // everything on: stage 3 needs memory to recover calldata and return data
+    fn default() -> Self {
+        Self {
+            enable_memory: true,
+            disable_stack: false,
+            disable_storage: false,
+            enable_return_data: true,
+            limit: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Tracer {
+    StructLogger(StructLoggerOptions),
+    #[serde(rename_all = "camelCase")]
+    CallTracer { only_top_call: bool, with_log: bool },
+    #[serde(rename_all = "camelCase")]
+    PrestateTracer { diff_mode: bool },
+    #[serde(rename = "4byteTracer")]
+    FourByteTracer,
+    Js { code: String },                  // custom JavaScript tracer source
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Tracer::StructLogger(StructLoggerOptions::default())
+    }
+}
+
+// second param of debug_traceTransaction
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TracerOptions {
+    pub tracer: Tracer,
+    pub timeout: Option<String>,          // go duration string, e.g. "30s"
+}
+
+impl TracerOptions {
+    pub fn struct_logger(options: StructLoggerOptions) -> Self {
+        Self { tracer: Tracer::StructLogger(options), timeout: None }
+    }
+
+    pub fn with_timeout(mut self, timeout: &str) -> Self {
+        self.timeout = Some(timeout.to_string());
+        self
+    }
+
+    // true when the response carries structLogs that trace-ir can parse
+    pub fn is_struct_logger(&self) -> bool {
+        matches!(self.tracer, Tracer::StructLogger(_))
+    }
+}
+
+// wire format, fields are written in declaration order and unset ones are left out
+// so the same options always produce the same request bytes
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TraceParams<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tracer: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tracer_config: Option<TracerConfigParams>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enable_memory: Option<bool>,
+    // older erigon/geth only know the inverted flag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disable_memory: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disable_stack: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disable_storage: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enable_return_data: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TracerConfigParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    only_top_call: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    with_log: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff_mode: Option<bool>,
+}
+
+impl TracerOptions {
+    // what actually goes over the wire
+    pub(crate) fn params(&self) -> TraceParams<'_> {
+        let mut params = TraceParams {
+            tracer: None,
+            tracer_config: None,
+            enable_memory: None,
+            disable_memory: None,
+            disable_stack: None,
+            disable_storage: None,
+            enable_return_data: None,
+            limit: None,
+            timeout: self.timeout.as_deref(),
+        };
+
+        match &self.tracer {
+            Tracer::StructLogger(opts) => {
+                params.enable_memory = Some(opts.enable_memory);
+                params.disable_memory = Some(!opts.enable_memory);
+                params.disable_stack = Some(opts.disable_stack);
+                params.disable_storage = Some(opts.disable_storage);
+                params.enable_return_data = Some(opts.enable_return_data);
+                params.limit = opts.limit.filter(|l| *l > 0);
+            }
+            Tracer::CallTracer { only_top_call, with_log } => {
+                params.tracer = Some("callTracer");
+                params.tracer_config = Some(TracerConfigParams {
+                    only_top_call: Some(*only_top_call),
+                    with_log: Some(*with_log),
+                    diff_mode: None,
+                });
+            }
+            Tracer::PrestateTracer { diff_mode } => {
+                params.tracer = Some("prestateTracer");
+                params.tracer_config = Some(TracerConfigParams {
+                    only_top_call: None,
+                    with_log: None,
+                    diff_mode: Some(*diff_mode),
+                });
+            }
+            Tracer::FourByteTracer => params.tracer = Some("4byteTracer"),
+            Tracer::Js { code } => params.tracer = Some(code),
+        }
+
+        params
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_struct_logger_params() {
+        let json = serde_json::to_string(&TracerOptions::default().with_timeout("30s").params()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"enableMemory":true,"disableMemory":false,"disableStack":false,"disableStorage":false,"enableReturnData":true,"timeout":"30s"}"#
+        );
+    }
+
+    #[test]
+    fn test_builtin_tracer_params() {
+        let call = TracerOptions {
+            tracer: Tracer::CallTracer { only_top_call: false, with_log: true },
+            timeout: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&call.params()).unwrap(),
+            r#"{"tracer":"callTracer","tracerConfig":{"onlyTopCall":false,"withLog":true}}"#
+        );
+
+        let prestate = TracerOptions {
+            tracer: Tracer::PrestateTracer { diff_mode: true },
+            timeout: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&prestate.params()).unwrap(),
+            r#"{"tracer":"prestateTracer","tracerConfig":{"diffMode":true}}"#
+        );
+    }
+
+    #[test]
+    fn test_params_are_reproducible() {
+        // the cache and the artifact hashes depend on these exact bytes
+        let struct_logger = TracerOptions::struct_logger(StructLoggerOptions {
+            enable_memory: false,
+            limit: Some(100),
+            ..StructLoggerOptions::default()
+        });
+        assert_eq!(
+            crate::debug_trace_payload("0xabc", &struct_logger),
+            r#"{"jsonrpc":"2.0","id":1,"method":"debug_traceTransaction","params":["0xabc",{"enableMemory":false,"disableMemory":true,"disableStack":false,"disableStorage":false,"enableReturnData":true,"limit":100}]}"#
+        );
+
+        let js = TracerOptions {
+            tracer: Tracer::Js { code: "{data: [], step: function() {}}".to_string() },
+            timeout: Some("5s".to_string()),
+        };
+        assert_eq!(
+            crate::debug_trace_payload("0xabc", &js),
+            r#"{"jsonrpc":"2.0","id":1,"method":"debug_traceTransaction","params":["0xabc",{"tracer":"{data: [], step: function() {}}","timeout":"5s"}]}"#
+        );
+    }
+}
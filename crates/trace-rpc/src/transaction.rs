@@ -0,0 +1,122 @@
+use std::path::Path;
+use alloy_primitives::{Address, B256, Bytes};
+use anyhow::{Result, Context, bail};
+use serde::Deserialize;
+use trace_ir::{CallFrame, Word, open_artifact};
+
+use crate::RpcResponse;
+
+// the parts of eth_getTransactionByHash a call tree needs for its root frame
+#[derive(Clone, Debug, Deserialize)]
+pub struct Transaction {
+    pub from: Address,
+    pub to: Option<Address>,            // None for contract creations
+    pub value: Word,
+    pub input: Bytes,
+}
+
+// the parts of a saved receipt.json analysis needs, everything else is skipped
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Receipt {
+    pub from: Address,
+    pub to: Option<Address>,
+    pub contract_address: Option<Address>,
+    pub status: Option<String>,         // 0x1/0x0, missing before byzantium
+    #[serde(default)]
+    pub logs: Vec<ReceiptLog>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReceiptLog {
+    pub address: Address,
+    pub topics: Vec<B256>,
+    pub data: Bytes,
+}
+
+impl Receipt {
+    pub fn read(path: &Path) -> Result<Self> {
+        let reader = open_artifact(path).context("could not open receipt")?;
+        let response: RpcResponse<Receipt> = serde_json::from_reader(reader)
+            .context("invalid receipt")?;
+        response.result.context("node has no receipt for this tx (not mined?)")
+    }
+
+    // the account the tx executed against, the new contract for creations
+    pub fn target(&self) -> Option<Address> {
+        self.to.or(self.contract_address)
+    }
+
+    // integrity check: the logs that survive in the trace must be the receipt's, in order.
+    // the tree needs the receipt's from/target as its root, see CallTreeBuilder::with_transaction
+    pub fn verify_logs(&self, root: &CallFrame) -> Result<()> {
+        let kept: Vec<_> = root.logs_in_order().into_iter().filter(|log| !log.discarded).collect();
+        if kept.len() != self.logs.len() {
+            bail!("trace keeps {} logs but the receipt has {}", kept.len(), self.logs.len());
+        }
+
+        for (i, (ours, theirs)) in kept.iter().zip(&self.logs).enumerate() {
+            if ours.address != theirs.address {
+                bail!("log {} comes from {} in the trace but from {} in the receipt", i, ours.address, theirs.address);
+            }
+            if ours.topics != theirs.topics {
+                bail!("log {} has different topics in the trace and the receipt", i);
+            }
+            if ours.data != theirs.data.as_ref() {
+                bail!("log {} has different data in the trace and the receipt", i);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn transaction_payload(tx_hash: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","id":1,"method":"eth_getTransactionByHash","params":["{}"]}}"#,
+        tx_hash
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_receipt_parties() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("receipt.json");
+        std::fs::write(&path, r#"{"jsonrpc":"2.0","id":"1","result":{
+            "from":"0x00000000000000000000000000000000000000aa","to":null,
+            "contractAddress":"0x00000000000000000000000000000000000000dd","status":"0x1","logs":[]}}"#).unwrap();
+
+        let receipt = Receipt::read(&path).unwrap();
+        assert_eq!(receipt.from, Address::with_last_byte(0xaa));
+        assert_eq!(receipt.target(), Some(Address::with_last_byte(0xdd)));
+    }
+
+    #[test]
+    fn test_verifies_logs() {
+        let receipt: Receipt = serde_json::from_str(r#"{
+            "from":"0x00000000000000000000000000000000000000aa","to":"0x00000000000000000000000000000000000000bb",
+            "logs":[{"address":"0x00000000000000000000000000000000000000bb","topics":[],"data":"0xabcd"}]}"#).unwrap();
+
+        let mut root = CallFrame::new(trace_ir::CallType::Root, receipt.from, Address::with_last_byte(0xbb), 0);
+        root.logs.push(trace_ir::Log {
+            address: Address::with_last_byte(0xbb),
+            topics: Vec::new(),
+            data: vec![0xab, 0xcd],
+            position: 0,
+            discarded: false,
+            decoded: None,
+        });
+        assert!(receipt.verify_logs(&root).is_ok());
+
+        root.logs[0].data.pop();
+        assert!(receipt.verify_logs(&root).is_err());
+
+        // a discarded log must not be in the receipt at all
+        root.logs[0].discarded = true;
+        assert!(receipt.verify_logs(&root).unwrap_err().to_string().contains("keeps 0 logs"));
+    }
+}
@@ -1,17 +1,18 @@
 use core::fmt;
-use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 use anyhow::{Result, Context};
 use serde::{Deserialize, Deserializer};
 use serde::de::{self, Visitor, MapAccess};
-use serde_json;
+use trace_ir::open_artifact;
+
+use crate::error::{RpcError, RpcErrorObject};
 
 
 
 // checks for truncated or corrupted JSON
 fn validate_json_well_formed(path: &Path) -> Result<()> {
-    let file = File::open(path)
+    let file = open_artifact(path)
         .context("could not open trace file for validation")?;
     let reader = BufReader::new(file);
 
@@ -31,21 +32,31 @@ fn validate_json_well_formed(path: &Path) -> Result<()> {
 }
 
 fn validate_rpc_envelope(path: &Path)->Result<()> {
-    let file = File::open(path)?;
+    let file = open_artifact(path)?;
     let reader = BufReader::new(file);
 
     let mut de = serde_json::Deserializer::from_reader(reader);
-    de.deserialize_any(RpcEnvelopeVisitor)
+    let envelope = de.deserialize_any(RpcEnvelopeVisitor)
         .map_err(|e| anyhow::anyhow!("Invalid Rpc envelope: {}", e))?;
 
+    // keep the typed error reachable through downcast_ref
+    if let Some(err) = envelope.error {
+        return Err(anyhow::Error::new(err));
+    }
+
     Ok(())
 }
 
+// what the envelope visitor learned
+struct Envelope {
+    error: Option<RpcError>,
+}
+
 
 struct RpcEnvelopeVisitor ;
 
 impl<'de> Visitor<'de> for RpcEnvelopeVisitor {
-    type Value= ();
+    type Value= Envelope;
 
     fn expecting(&self, formatter: &mut fmt::Formatter)-> fmt::Result {
         formatter.write_str("A valid JSON-RPC 2.0 response object")
@@ -56,7 +67,7 @@ impl<'de> Visitor<'de> for RpcEnvelopeVisitor {
         A: MapAccess<'de>
     {
         let mut has_result = false;
-        let mut has_error = false;
+        let mut error: Option<RpcError> = None;
         let mut has_jsonrpc = false;
 
         while let Some(key) = map.next_key::<String>()? {
@@ -75,9 +86,8 @@ impl<'de> Visitor<'de> for RpcEnvelopeVisitor {
                     map.next_value::<de::IgnoredAny>()?;
                 },
                 "error" => {
-                    has_error = true;
-                    map.next_value::<de::IgnoredAny>()?;
-
+                    // some nodes send "error": null next to a result
+                    error = map.next_value::<Option<RpcErrorObject>>()?.map(RpcError::from);
                 },
                 _ =>{
                     map.next_value::<de::IgnoredAny>()?;
@@ -90,14 +100,14 @@ impl<'de> Visitor<'de> for RpcEnvelopeVisitor {
         if !has_jsonrpc {
             return Err(de::Error::custom("Missing/Invalid 'jsonrpc' field"));
         }
-        if has_error {
-            return Err(de::Error::custom("Rpc returned an error"));
+        if error.is_some() {
+            return Ok(Envelope { error });
         }
         if !has_result {
             return Err(de::Error::custom("Rpc response missing 'result' field"));
         }
 
-        Ok(())
+        Ok(Envelope { error: None })
     }
 
 }